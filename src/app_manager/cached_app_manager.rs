@@ -0,0 +1,154 @@
+use super::{AppManager, AppManagerError, SafeAppManager};
+use crate::config::AppConfig;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+type Cache = RwLock<HashMap<String, (Instant, Option<AppConfig>)>>;
+
+/// Remembers lookups of another backend for `ttl`, including apps that were not found.
+pub struct CachedAppManager {
+    inner: SafeAppManager,
+    ttl: Duration,
+    by_id: Cache,
+    by_key: Cache,
+}
+
+impl CachedAppManager {
+    pub fn new(inner: SafeAppManager, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            by_id: RwLock::new(HashMap::new()),
+            by_key: RwLock::new(HashMap::new()),
+        }
+    }
+
+    async fn cached(&self, cache: &Cache, lookup: &str) -> Option<Option<AppConfig>> {
+        let cache = cache.read().await;
+        cache
+            .get(lookup)
+            .filter(|(expires_at, _)| *expires_at > Instant::now())
+            .map(|(_, app)| app.clone())
+    }
+
+    async fn store(&self, cache: &Cache, lookup: &str, app: &Option<AppConfig>) {
+        let mut cache = cache.write().await;
+        let now = Instant::now();
+        cache.retain(|_, (expires_at, _)| *expires_at > now);
+        cache.insert(lookup.to_string(), (now + self.ttl, app.clone()));
+    }
+}
+
+#[async_trait]
+impl AppManager for CachedAppManager {
+    async fn find_by_id(&self, app_id: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        if let Some(app) = self.cached(&self.by_id, app_id).await {
+            return Ok(app);
+        }
+        let app = self.inner.find_by_id(app_id).await?;
+        self.store(&self.by_id, app_id, &app).await;
+        Ok(app)
+    }
+
+    async fn find_by_key(&self, key: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        if let Some(app) = self.cached(&self.by_key, key).await {
+            return Ok(app);
+        }
+        let app = self.inner.find_by_key(key).await?;
+        self.store(&self.by_key, key, &app).await;
+        Ok(app)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// Backend whose apps can change under the cache, counting its lookups.
+    #[derive(Default)]
+    struct Backend {
+        apps: Mutex<Vec<AppConfig>>,
+        lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AppManager for Backend {
+        async fn find_by_id(&self, app_id: &str) -> Result<Option<AppConfig>, AppManagerError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let apps = self.apps.lock().unwrap();
+            Ok(apps.iter().find(|app| app.id == app_id).cloned())
+        }
+
+        async fn find_by_key(&self, key: &str) -> Result<Option<AppConfig>, AppManagerError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let apps = self.apps.lock().unwrap();
+            Ok(apps.iter().find(|app| app.key == key).cloned())
+        }
+    }
+
+    fn app(id: &str, secret: &str) -> AppConfig {
+        serde_json::from_value(json!({ "id": id, "key": format!("key-{}", id), "secret": secret }))
+            .unwrap()
+    }
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    #[tokio::test]
+    async fn cached_apps_expire() {
+        let backend = Arc::new(Backend::default());
+        backend.apps.lock().unwrap().push(app("1", "old"));
+        let manager = CachedAppManager::new(backend.clone(), TTL);
+
+        assert_eq!(
+            manager.find_by_id("1").await.unwrap().unwrap().secret,
+            "old"
+        );
+        assert_eq!(
+            manager.find_by_key("key-1").await.unwrap().unwrap().secret,
+            "old"
+        );
+        *backend.apps.lock().unwrap() = vec![app("1", "new")];
+        assert_eq!(
+            manager.find_by_id("1").await.unwrap().unwrap().secret,
+            "old"
+        );
+        assert_eq!(
+            manager.find_by_key("key-1").await.unwrap().unwrap().secret,
+            "old"
+        );
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(TTL * 2).await;
+        assert_eq!(
+            manager.find_by_id("1").await.unwrap().unwrap().secret,
+            "new"
+        );
+        assert_eq!(
+            manager.find_by_key("key-1").await.unwrap().unwrap().secret,
+            "new"
+        );
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn missing_apps_are_cached_until_they_expire() {
+        let backend = Arc::new(Backend::default());
+        let manager = CachedAppManager::new(backend.clone(), TTL);
+
+        assert!(manager.find_by_id("2").await.unwrap().is_none());
+        assert!(manager.find_by_key("key-2").await.unwrap().is_none());
+        backend.apps.lock().unwrap().push(app("2", "secret"));
+        assert!(manager.find_by_id("2").await.unwrap().is_none());
+        assert!(manager.find_by_key("key-2").await.unwrap().is_none());
+        assert_eq!(backend.lookups.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(TTL * 2).await;
+        assert!(manager.find_by_id("2").await.unwrap().is_some());
+        assert!(manager.find_by_key("key-2").await.unwrap().is_some());
+    }
+}
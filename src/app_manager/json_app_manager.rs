@@ -0,0 +1,164 @@
+use super::{AppKeyIndex, AppManager, AppManagerError};
+use crate::config::{validate_apps, AppConfig};
+use crate::log::Log;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+
+struct LoadedApps {
+    apps: AppKeyIndex,
+    modified: Option<SystemTime>,
+}
+
+/// Apps read from a JSON array in a file that is re-read when it changes.
+pub struct JsonAppManager {
+    path: PathBuf,
+    loaded: RwLock<LoadedApps>,
+}
+
+impl JsonAppManager {
+    pub async fn load(path: PathBuf) -> Result<Arc<Self>, AppManagerError> {
+        let loaded = read_apps(&path).await?;
+        Log::info(format!(
+            "Loaded {} apps from {}",
+            loaded.apps.len(),
+            path.display()
+        ));
+        Ok(Arc::new(Self {
+            path,
+            loaded: RwLock::new(loaded),
+        }))
+    }
+
+    /// Polls the file's modification time and reloads it when it changes.
+    /// A file that fails to parse is reported and the previous apps are kept.
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                manager.reload_if_changed().await;
+            }
+        });
+    }
+
+    async fn reload_if_changed(&self) {
+        let modified = modified_time(&self.path).await;
+        if modified == self.loaded.read().await.modified {
+            return;
+        }
+        match read_apps(&self.path).await {
+            Ok(loaded) => {
+                Log::info(format!(
+                    "Reloaded {} apps from {}",
+                    loaded.apps.len(),
+                    self.path.display()
+                ));
+                *self.loaded.write().await = loaded;
+            }
+            Err(e) => {
+                Log::error(format!("{}; keeping the previous apps", e));
+                // Only report a broken file once per change
+                self.loaded.write().await.modified = modified;
+            }
+        }
+    }
+}
+
+async fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+async fn read_apps(path: &PathBuf) -> Result<LoadedApps, AppManagerError> {
+    let load_error = |message: String| AppManagerError::Load {
+        path: path.display().to_string(),
+        message,
+    };
+    let modified = modified_time(path).await;
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| load_error(e.to_string()))?;
+    let apps: Vec<AppConfig> =
+        serde_json::from_str(&contents).map_err(|e| load_error(e.to_string()))?;
+    validate_apps(&apps).map_err(load_error)?;
+    Ok(LoadedApps {
+        apps: AppKeyIndex::new(apps),
+        modified,
+    })
+}
+
+#[async_trait]
+impl AppManager for JsonAppManager {
+    async fn find_by_id(&self, app_id: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        let loaded = self.loaded.read().await;
+        Ok(loaded.apps.by_id(app_id).cloned())
+    }
+
+    async fn find_by_key(&self, key: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        let loaded = self.loaded.read().await;
+        Ok(loaded.apps.by_key(key).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// Writes `contents` and moves the modification time forward, so the
+    /// change is seen even on filesystems with coarse timestamps.
+    fn write(path: &PathBuf, contents: &str, later_by: u64) {
+        std::fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(later_by))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn reloads_the_file_when_it_changes() {
+        let path =
+            std::env::temp_dir().join(format!("sockudo-json-apps-{}.json", std::process::id()));
+        write(
+            &path,
+            r#"[{"id": "1", "key": "key-1", "secret": "secret-1"}]"#,
+            0,
+        );
+        let manager = JsonAppManager::load(path.clone()).await.unwrap();
+        assert_eq!(manager.find_by_key("key-1").await.unwrap().unwrap().id, "1");
+        assert!(manager.find_by_id("2").await.unwrap().is_none());
+
+        write(
+            &path,
+            r#"[{"id": "2", "key": "key-2", "secret": "secret-2"}]"#,
+            1,
+        );
+        manager.reload_if_changed().await;
+        assert!(manager.find_by_id("1").await.unwrap().is_none());
+        assert_eq!(manager.find_by_key("key-2").await.unwrap().unwrap().id, "2");
+
+        // Apps with a duplicate key are refused and the last good file stays in use
+        write(
+            &path,
+            r#"[{"id": "3", "key": "key", "secret": "s"}, {"id": "4", "key": "key", "secret": "s"}]"#,
+            2,
+        );
+        manager.reload_if_changed().await;
+        assert!(manager.find_by_id("3").await.unwrap().is_none());
+        assert_eq!(manager.find_by_id("2").await.unwrap().unwrap().key, "key-2");
+
+        let _ = std::fs::remove_file(path);
+    }
+}
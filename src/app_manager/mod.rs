@@ -0,0 +1,95 @@
+pub mod cached_app_manager;
+pub mod json_app_manager;
+pub mod sqlite_app_manager;
+pub mod static_app_manager;
+
+use crate::config::{AppConfig, AppManagerConfig, AppManagerDriver, ServerConfig};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppManagerError {
+    #[error("Failed to read apps from {path}: {message}")]
+    Load { path: String, message: String },
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Invalid app {app_id}: {message}")]
+    InvalidApp { app_id: String, message: String },
+}
+
+/// Source of app credentials and settings.
+#[async_trait]
+pub trait AppManager: Send + Sync {
+    async fn find_by_id(&self, app_id: &str) -> Result<Option<AppConfig>, AppManagerError>;
+    async fn find_by_key(&self, key: &str) -> Result<Option<AppConfig>, AppManagerError>;
+}
+
+pub type SafeAppManager = Arc<dyn AppManager>;
+
+/// In-memory apps indexed by id and by key.
+pub struct AppKeyIndex {
+    apps: HashMap<String, AppConfig>,
+    ids_by_key: HashMap<String, String>,
+}
+
+impl AppKeyIndex {
+    pub fn new(apps: Vec<AppConfig>) -> Self {
+        let ids_by_key = apps
+            .iter()
+            .map(|app| (app.key.clone(), app.id.clone()))
+            .collect();
+        let apps = apps.into_iter().map(|app| (app.id.clone(), app)).collect();
+        Self { apps, ids_by_key }
+    }
+
+    pub fn by_id(&self, app_id: &str) -> Option<&AppConfig> {
+        self.apps.get(app_id)
+    }
+
+    pub fn by_key(&self, key: &str) -> Option<&AppConfig> {
+        self.ids_by_key
+            .get(key)
+            .and_then(|app_id| self.apps.get(app_id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.apps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.apps.is_empty()
+    }
+}
+
+/// Builds the backend selected by `[app_manager]`, wrapped in a TTL cache when one is set.
+pub async fn create_app_manager(config: &ServerConfig) -> Result<SafeAppManager, AppManagerError> {
+    let AppManagerConfig {
+        driver,
+        path,
+        cache_ttl,
+        watch_interval,
+    } = &config.app_manager;
+    let manager: SafeAppManager = match driver {
+        AppManagerDriver::Static => Arc::new(static_app_manager::StaticAppManager::new(
+            config.apps.clone(),
+        )),
+        AppManagerDriver::Json => {
+            let manager =
+                json_app_manager::JsonAppManager::load(path.clone().unwrap_or_default()).await?;
+            manager.watch(Duration::from_secs(*watch_interval));
+            manager
+        }
+        AppManagerDriver::Sqlite => Arc::new(
+            sqlite_app_manager::SqliteAppManager::open(path.clone().unwrap_or_default()).await?,
+        ),
+    };
+    if *cache_ttl == 0 {
+        return Ok(manager);
+    }
+    Ok(Arc::new(cached_app_manager::CachedAppManager::new(
+        manager,
+        Duration::from_secs(*cache_ttl),
+    )))
+}
@@ -0,0 +1,280 @@
+use super::{AppManager, AppManagerError};
+use crate::config::{validate_app, AppConfig, AppLimits, AppTimeouts};
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS apps (
+    id TEXT PRIMARY KEY,
+    key TEXT NOT NULL UNIQUE,
+    secret TEXT NOT NULL,
+    enabled INTEGER NOT NULL DEFAULT 1,
+    enable_client_messages INTEGER NOT NULL DEFAULT 1,
+    max_connections INTEGER,
+    max_buffer_size INTEGER,
+    backpressure_policy TEXT,
+    activity_timeout INTEGER,
+    pong_timeout INTEGER,
+    webhooks TEXT,
+    authorizer TEXT,
+    open_auth INTEGER NOT NULL DEFAULT 0,
+    channel_rules TEXT,
+    jwt TEXT
+)";
+
+const SELECT_APP: &str = "SELECT id, key, secret, enabled, enable_client_messages, \
+     max_connections, max_buffer_size, backpressure_policy, webhooks, authorizer, \
+     channel_rules, jwt, open_auth, activity_timeout, pong_timeout FROM apps";
+
+/// Apps stored in the `apps` table of a SQLite database, queried on every lookup.
+pub struct SqliteAppManager {
+    connection: Arc<Mutex<Connection>>,
+}
+
+struct AppRow {
+    id: String,
+    key: String,
+    secret: String,
+    enabled: bool,
+    enable_client_messages: bool,
+    max_connections: Option<usize>,
+    max_buffer_size: Option<usize>,
+    backpressure_policy: Option<String>,
+    /// JSON array of `{"url", "event_types"}` objects.
+    webhooks: Option<String>,
+    /// JSON object with the `[apps.authorizer]` settings.
+    authorizer: Option<String>,
+    /// Path of the channel rules file.
+    channel_rules: Option<String>,
+    /// JSON object with the `[apps.jwt]` settings.
+    jwt: Option<String>,
+    open_auth: bool,
+    activity_timeout: Option<u64>,
+    pong_timeout: Option<u64>,
+}
+
+impl SqliteAppManager {
+    /// Opens the database, creating the `apps` table if it does not exist yet.
+    pub async fn open(path: PathBuf) -> Result<Self, AppManagerError> {
+        let connection = tokio::task::spawn_blocking(move || {
+            let connection = Connection::open(&path)?;
+            connection.execute(SCHEMA, [])?;
+            Ok::<_, rusqlite::Error>(connection)
+        })
+        .await
+        .map_err(|e| AppManagerError::Database(e.to_string()))?
+        .map_err(|e| AppManagerError::Database(e.to_string()))?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn find_by(
+        &self,
+        column: &'static str,
+        value: &str,
+    ) -> Result<Option<AppConfig>, AppManagerError> {
+        let connection = self.connection.clone();
+        let value = value.to_string();
+        let row = tokio::task::spawn_blocking(move || {
+            let connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            connection
+                .query_row(
+                    &format!("{} WHERE {} = ?1", SELECT_APP, column),
+                    [value],
+                    |row| {
+                        Ok(AppRow {
+                            id: row.get(0)?,
+                            key: row.get(1)?,
+                            secret: row.get(2)?,
+                            enabled: row.get(3)?,
+                            enable_client_messages: row.get(4)?,
+                            max_connections: row.get(5)?,
+                            max_buffer_size: row.get(6)?,
+                            backpressure_policy: row.get(7)?,
+                            webhooks: row.get(8)?,
+                            authorizer: row.get(9)?,
+                            channel_rules: row.get(10)?,
+                            jwt: row.get(11)?,
+                            open_auth: row.get(12)?,
+                            activity_timeout: row.get(13)?,
+                            pong_timeout: row.get(14)?,
+                        })
+                    },
+                )
+                .optional()
+        })
+        .await
+        .map_err(|e| AppManagerError::Database(e.to_string()))?
+        .map_err(|e| AppManagerError::Database(e.to_string()))?;
+        row.map(AppConfig::try_from).transpose()
+    }
+}
+
+impl TryFrom<AppRow> for AppConfig {
+    type Error = AppManagerError;
+
+    fn try_from(row: AppRow) -> Result<Self, Self::Error> {
+        let defaults = AppLimits::default();
+        let backpressure_policy = match row.backpressure_policy {
+            Some(policy) => policy
+                .parse()
+                .map_err(|message| AppManagerError::InvalidApp {
+                    app_id: row.id.clone(),
+                    message,
+                })?,
+            None => defaults.backpressure_policy,
+        };
+        let webhooks = match row.webhooks.as_deref() {
+            Some(webhooks) => {
+                serde_json::from_str(webhooks).map_err(|e| AppManagerError::InvalidApp {
+                    app_id: row.id.clone(),
+                    message: format!("invalid webhooks: {}", e),
+                })?
+            }
+            None => Vec::new(),
+        };
+        let authorizer = match row.authorizer.as_deref() {
+            Some(authorizer) => {
+                serde_json::from_str(authorizer).map_err(|e| AppManagerError::InvalidApp {
+                    app_id: row.id.clone(),
+                    message: format!("invalid authorizer: {}", e),
+                })?
+            }
+            None => None,
+        };
+        let jwt = match row.jwt.as_deref() {
+            Some(jwt) => serde_json::from_str(jwt).map_err(|e| AppManagerError::InvalidApp {
+                app_id: row.id.clone(),
+                message: format!("invalid jwt: {}", e),
+            })?,
+            None => None,
+        };
+        let app = AppConfig {
+            id: row.id,
+            key: row.key,
+            secret: row.secret,
+            enabled: row.enabled,
+            enable_client_messages: row.enable_client_messages,
+            limits: AppLimits {
+                max_connections: row.max_connections,
+                max_buffer_size: row.max_buffer_size.unwrap_or(defaults.max_buffer_size),
+                backpressure_policy,
+            },
+            timeouts: AppTimeouts {
+                activity_timeout: row.activity_timeout,
+                pong_timeout: row.pong_timeout,
+            },
+            webhooks,
+            authorizer,
+            open_auth: row.open_auth,
+            channel_rules: row.channel_rules.map(PathBuf::from),
+            jwt,
+        };
+        // Rows get the same checks as apps from the config file
+        validate_app(&app).map_err(|message| AppManagerError::InvalidApp {
+            app_id: app.id.clone(),
+            message,
+        })?;
+        Ok(app)
+    }
+}
+
+#[async_trait]
+impl AppManager for SqliteAppManager {
+    async fn find_by_id(&self, app_id: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        self.find_by("id", app_id).await
+    }
+
+    async fn find_by_key(&self, key: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        self.find_by("key", key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::BackpressurePolicy;
+
+    async fn manager() -> SqliteAppManager {
+        let manager = SqliteAppManager::open(PathBuf::from(":memory:"))
+            .await
+            .unwrap();
+        manager
+            .connection
+            .lock()
+            .unwrap()
+            .execute_batch(
+                r#"
+                INSERT INTO apps (id, key, secret) VALUES ('1', 'key-1', 'secret-1');
+                INSERT INTO apps (id, key, secret, enabled, max_connections, max_buffer_size,
+                    backpressure_policy, activity_timeout, webhooks, jwt, open_auth)
+                VALUES ('2', 'key-2', 'secret-2', 0, 100, 8, 'drop_oldest', 60,
+                    '[{"url": "https://example.com/hooks", "event_types": ["channel_occupied"]}]',
+                    '{"secret": "jwt-secret", "required": true}', 1);
+                INSERT INTO apps (id, key, secret, backpressure_policy)
+                VALUES ('3', 'key-3', 'secret-3', 'drop_everything');
+                INSERT INTO apps (id, key, secret, max_buffer_size)
+                VALUES ('4', 'key-4', 'secret-4', 0);
+                INSERT INTO apps (id, key, secret) VALUES ('5', 'key-5', '');
+                INSERT INTO apps (id, key, secret, webhooks)
+                VALUES ('6', 'key-6', 'secret-6', '[{"url": "ftp://example.com/hooks"}]');
+                "#,
+            )
+            .unwrap();
+        manager
+    }
+
+    #[tokio::test]
+    async fn finds_apps_by_id_and_key() {
+        let manager = manager().await;
+        let by_id = manager.find_by_id("1").await.unwrap().unwrap();
+        let by_key = manager.find_by_key("key-1").await.unwrap().unwrap();
+        assert_eq!(by_id, by_key);
+        assert_eq!(by_id.secret, "secret-1");
+        assert!(by_id.enabled);
+        assert_eq!(by_id.limits, AppLimits::default());
+        assert!(by_id.webhooks.is_empty() && by_id.jwt.is_none());
+
+        assert!(manager.find_by_id("key-1").await.unwrap().is_none());
+        assert!(manager.find_by_key("1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_optional_columns() {
+        let app = manager().await.find_by_key("key-2").await.unwrap().unwrap();
+        assert_eq!(app.id, "2");
+        assert!(!app.enabled);
+        assert!(app.open_auth);
+        assert_eq!(app.limits.max_connections, Some(100));
+        assert_eq!(app.limits.max_buffer_size, 8);
+        assert_eq!(
+            app.limits.backpressure_policy,
+            BackpressurePolicy::DropOldest
+        );
+        assert_eq!(app.timeouts.activity_timeout, Some(60));
+        assert_eq!(app.timeouts.pong_timeout, None);
+        assert_eq!(app.webhooks[0].url, "https://example.com/hooks");
+        assert!(app.jwt.is_some_and(|jwt| jwt.required));
+    }
+
+    #[tokio::test]
+    async fn invalid_rows_are_errors() {
+        let manager = manager().await;
+        for (id, error) in [
+            ("3", "unknown backpressure policy"),
+            ("4", "limits.max_buffer_size must be at least 1"),
+            ("5", "secret must not be empty"),
+            ("6", "webhooks[0].url must be an http:// or https:// URL"),
+        ] {
+            match manager.find_by_id(id).await {
+                Err(AppManagerError::InvalidApp { app_id, message }) => {
+                    assert_eq!(app_id, id);
+                    assert!(message.starts_with(error), "{}", message);
+                }
+                other => panic!("expected app {} to be invalid, got {:?}", id, other),
+            }
+        }
+    }
+}
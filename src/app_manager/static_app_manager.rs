@@ -0,0 +1,27 @@
+use super::{AppKeyIndex, AppManager, AppManagerError};
+use crate::config::AppConfig;
+use async_trait::async_trait;
+
+/// Apps listed in the server config file.
+pub struct StaticAppManager {
+    apps: AppKeyIndex,
+}
+
+impl StaticAppManager {
+    pub fn new(apps: Vec<AppConfig>) -> Self {
+        Self {
+            apps: AppKeyIndex::new(apps),
+        }
+    }
+}
+
+#[async_trait]
+impl AppManager for StaticAppManager {
+    async fn find_by_id(&self, app_id: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        Ok(self.apps.by_id(app_id).cloned())
+    }
+
+    async fn find_by_key(&self, key: &str) -> Result<Option<AppConfig>, AppManagerError> {
+        Ok(self.apps.by_key(key).cloned())
+    }
+}
@@ -1,15 +1,50 @@
-use crate::channel::{create_channel_manager, SafeChannelManager};
-use crate::connection::{create_connection_manager, SafeConnectionManager};
+use crate::app_manager::{create_app_manager, AppManagerError, SafeAppManager};
+use crate::auth::authorizer::AuthorizerConfig;
+use crate::auth::jwt::JwtVerifier;
+use crate::auth::rules::ChannelRules;
+use crate::channel::{
+    create_channel_manager, Channel, ChannelType, Joined, Left, PresenceUser, SafeChannelManager,
+};
+use crate::config::{AppConfig, ServerConfig, TimeoutConfig};
+use crate::connection::{
+    create_connection_manager, BackpressurePolicy, SafeConnection, SafeConnectionManager,
+    OUTBOUND_QUEUE_SIZE,
+};
+use crate::error::AppError;
+use crate::webhook::queue::SafeWebhookQueue;
+use crate::webhook::sender::WebhookSender;
+use crate::webhook::WebhookEvent;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// Seconds of inactivity after which the server pings a client.
+pub const DEFAULT_ACTIVITY_TIMEOUT: u64 = 120;
+/// Seconds a client has to answer a server `pusher:ping` before it is disconnected.
+pub const DEFAULT_PONG_TIMEOUT: u64 = 30;
+
 pub struct Application {
     pub app_id: String,
     pub key: String,
     pub secret: String,
+    pub activity_timeout: u64,
+    pub pong_timeout: u64,
+    pub enabled: bool,
+    pub enable_client_messages: bool,
+    /// Maximum concurrent connections; unlimited when `None`.
+    pub max_connections: Option<usize>,
+    /// Maximum number of frames queued per connection.
+    pub max_buffer_size: usize,
+    pub backpressure_policy: BackpressurePolicy,
     pub channel_manager: SafeChannelManager,
     pub connection_manager: SafeConnectionManager,
+    /// Delivers events to the app's webhook URLs; `None` when it has none.
+    pub webhooks: Option<WebhookSender>,
+    pub authorizer: Option<AuthorizerConfig>,
+    pub open_auth: bool,
+    pub channel_rules: Option<ChannelRules>,
+    /// Verifies JWTs sent instead of signatures; `None` when the app takes none.
+    pub jwt: Option<JwtVerifier>,
 }
 
 impl Application {
@@ -18,55 +53,272 @@ impl Application {
             app_id,
             key,
             secret,
+            activity_timeout: DEFAULT_ACTIVITY_TIMEOUT,
+            pong_timeout: DEFAULT_PONG_TIMEOUT,
+            enabled: true,
+            enable_client_messages: true,
+            max_connections: None,
+            max_buffer_size: OUTBOUND_QUEUE_SIZE,
+            backpressure_policy: BackpressurePolicy::default(),
             channel_manager: create_channel_manager(),
             connection_manager: create_connection_manager(),
+            webhooks: None,
+            authorizer: None,
+            open_auth: false,
+            channel_rules: None,
+            jwt: None,
+        }
+    }
+
+    pub fn from_config(
+        config: &AppConfig,
+        timeouts: &TimeoutConfig,
+        webhook_queue: &SafeWebhookQueue,
+    ) -> Result<Self, AppError> {
+        let mut application =
+            Self::new(config.id.clone(), config.key.clone(), config.secret.clone())
+                .with_timeouts(
+                    config
+                        .timeouts
+                        .activity_timeout
+                        .unwrap_or(timeouts.activity_timeout),
+                    config
+                        .timeouts
+                        .pong_timeout
+                        .unwrap_or(timeouts.pong_timeout),
+                )
+                .with_backpressure(
+                    config.limits.max_buffer_size,
+                    config.limits.backpressure_policy,
+                );
+        application.enabled = config.enabled;
+        application.enable_client_messages = config.enable_client_messages;
+        application.max_connections = config.limits.max_connections;
+        application.authorizer = config.authorizer.clone();
+        application.open_auth = config.open_auth;
+        if let Some(path) = &config.channel_rules {
+            let rules = ChannelRules::load(path).map_err(|e| {
+                AppError::InternalServerError(format!(
+                    "Failed to load channel rules of app {}: {}",
+                    config.id, e
+                ))
+            })?;
+            application.channel_rules = Some(rules);
+        }
+        if let Some(jwt) = &config.jwt {
+            let verifier = JwtVerifier::load(jwt).map_err(|e| {
+                AppError::InternalServerError(format!(
+                    "Failed to load JWT keys of app {}: {}",
+                    config.id, e
+                ))
+            })?;
+            application.jwt = Some(verifier);
+        }
+        if !config.webhooks.is_empty() {
+            application.webhooks = Some(WebhookSender::new(
+                config.id.clone(),
+                config.key.clone(),
+                config.secret.clone(),
+                config.webhooks.clone(),
+                webhook_queue.clone(),
+            ));
+        }
+        Ok(application)
+    }
+
+    pub fn with_timeouts(mut self, activity_timeout: u64, pong_timeout: u64) -> Self {
+        self.activity_timeout = activity_timeout;
+        self.pong_timeout = pong_timeout;
+        self
+    }
+
+    pub fn with_backpressure(mut self, max_buffer_size: usize, policy: BackpressurePolicy) -> Self {
+        self.max_buffer_size = max_buffer_size;
+        self.backpressure_policy = policy;
+        self
+    }
+
+    /// Queues `event` for the app's webhooks, if it has any.
+    pub fn send_webhook(&self, event: WebhookEvent) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.send(event);
+        }
+    }
+
+    /// Adds the connection to a channel and sends the `channel_occupied` and
+    /// `member_added` webhooks the subscription caused.
+    pub async fn subscribe(
+        &self,
+        channel_name: &str,
+        channel_type: ChannelType,
+        connection: &SafeConnection,
+        member: Option<PresenceUser>,
+    ) -> Result<(Arc<dyn Channel>, Joined), AppError> {
+        let member_id = member.as_ref().map(|member| member.user_id.clone());
+        let (channel, joined) = self
+            .channel_manager
+            .subscribe(channel_name.to_string(), channel_type, connection, member)
+            .await?;
+        if joined.occupied {
+            self.send_webhook(WebhookEvent::channel_occupied(channel_name));
+        }
+        if let Some(user_id) = member_id.filter(|_| joined.new_member) {
+            self.send_webhook(WebhookEvent::member_added(channel_name, &user_id));
+        }
+        Ok((channel, joined))
+    }
+
+    /// Removes the socket from a channel and sends the `member_removed` and
+    /// `channel_vacated` webhooks that caused.
+    pub async fn unsubscribe(
+        &self,
+        channel_name: &str,
+        socket_id: &str,
+    ) -> Result<Option<(Arc<dyn Channel>, Left)>, AppError> {
+        let Some((channel, left)) = self
+            .channel_manager
+            .unsubscribe(channel_name, socket_id)
+            .await?
+        else {
+            return Ok(None);
+        };
+        if let Some(user) = &left.removed_member {
+            self.send_webhook(WebhookEvent::member_removed(channel_name, &user.user_id));
+        }
+        if left.vacated {
+            self.send_webhook(WebhookEvent::channel_vacated(channel_name));
         }
+        Ok(Some((channel, left)))
     }
 }
 
+/// Live apps, built from whatever the configured [`AppManager`](crate::app_manager::AppManager)
+/// backend returns.
+///
+/// Channels and connections are kept per app id, so an app whose settings change
+/// in the backend keeps its subscribers.
 pub struct ApplicationManager {
-    applications: RwLock<HashMap<String, Arc<Application>>>,
+    backend: SafeAppManager,
+    timeouts: TimeoutConfig,
+    webhook_queue: SafeWebhookQueue,
+    applications: RwLock<HashMap<String, (AppConfig, Arc<Application>)>>,
 }
 
 impl ApplicationManager {
-    pub fn new() -> Self {
-        let application = HashMap::from([(
-            "test".to_string(),
-            Arc::new(Application::new(
-                "test".to_string(),
-                "test".to_string(),
-                "test".to_string(),
-            )),
-        )]);
+    pub fn new(
+        backend: SafeAppManager,
+        timeouts: TimeoutConfig,
+        webhook_queue: SafeWebhookQueue,
+    ) -> Self {
         Self {
-            applications: RwLock::new(HashMap::from(application)),
+            backend,
+            timeouts,
+            webhook_queue,
+            applications: RwLock::new(HashMap::new()),
         }
     }
 
-    pub async fn add_application(&self, app_id: String, key: String, secret: String) {
-        let application = Arc::new(Application::new(app_id.clone(), key, secret));
-        let mut applications = self.applications.write().await;
-        applications.insert(app_id, application);
+    pub async fn get_application(
+        &self,
+        app_id: &str,
+    ) -> Result<Option<Arc<Application>>, AppError> {
+        match self.backend.find_by_id(app_id).await? {
+            Some(config) => Ok(Some(self.live_application(config).await?)),
+            None => Ok(None),
+        }
     }
 
-    pub async fn get_application(&self, app_id: &str) -> Option<Arc<Application>> {
-        let applications = self.applications.read().await;
-        applications.get(app_id).cloned()
+    pub async fn authenticate_key(&self, key: &str) -> Result<Option<Arc<Application>>, AppError> {
+        match self.backend.find_by_key(key).await? {
+            Some(config) => Ok(Some(self.live_application(config).await?)),
+            None => Ok(None),
+        }
     }
 
-    pub async fn remove_application(&self, app_id: &str) {
-        let mut applications = self.applications.write().await;
-        applications.remove(app_id);
-    }
+    /// Returns the running app for `config`, rebuilding it when its settings changed.
+    async fn live_application(&self, config: AppConfig) -> Result<Arc<Application>, AppError> {
+        if let Some((current, application)) = self.applications.read().await.get(&config.id) {
+            if *current == config {
+                return Ok(application.clone());
+            }
+        }
 
-    pub async fn authenticate_key(&self, key: &str) -> Option<Arc<Application>> {
-        let applications = self.applications.read().await;
-        applications.values().find(|app| app.key == key).cloned()
+        let mut applications = self.applications.write().await;
+        let mut application =
+            Application::from_config(&config, &self.timeouts, &self.webhook_queue)?;
+        if let Some((current, previous)) = applications.get(&config.id) {
+            if *current == config {
+                return Ok(previous.clone());
+            }
+            application.channel_manager = previous.channel_manager.clone();
+            application.connection_manager = previous.connection_manager.clone();
+        }
+        let application = Arc::new(application);
+        applications.insert(config.id.clone(), (config, application.clone()));
+        Ok(application)
     }
 }
 
 pub type SafeApplicationManager = Arc<ApplicationManager>;
 
-pub fn create_application_manager() -> SafeApplicationManager {
-    Arc::new(ApplicationManager::new())
+pub async fn create_application_manager(
+    config: &ServerConfig,
+    webhook_queue: SafeWebhookQueue,
+) -> Result<SafeApplicationManager, AppManagerError> {
+    let backend = create_app_manager(config).await?;
+    Ok(Arc::new(ApplicationManager::new(
+        backend,
+        config.timeouts.clone(),
+        webhook_queue,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppLimits, AppTimeouts, WebhookQueueConfig};
+    use crate::webhook::queue::WebhookQueue;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn app_timeouts_override_the_server_defaults() {
+        let queue = WebhookQueue::start(&WebhookQueueConfig {
+            path: PathBuf::from(":memory:"),
+            ..WebhookQueueConfig::default()
+        })
+        .await
+        .unwrap();
+        let defaults = TimeoutConfig {
+            activity_timeout: 120,
+            pong_timeout: 30,
+        };
+        let mut config = AppConfig {
+            id: "1".to_string(),
+            key: "key".to_string(),
+            secret: "secret".to_string(),
+            enabled: true,
+            enable_client_messages: true,
+            limits: AppLimits::default(),
+            timeouts: AppTimeouts::default(),
+            webhooks: Vec::new(),
+            authorizer: None,
+            open_auth: false,
+            channel_rules: None,
+            jwt: None,
+        };
+
+        let app = Application::from_config(&config, &defaults, &queue).unwrap();
+        assert_eq!((app.activity_timeout, app.pong_timeout), (120, 30));
+
+        config.timeouts = AppTimeouts {
+            activity_timeout: Some(5),
+            pong_timeout: None,
+        };
+        let app = Application::from_config(&config, &defaults, &queue).unwrap();
+        assert_eq!((app.activity_timeout, app.pong_timeout), (5, 30));
+
+        config.timeouts.pong_timeout = Some(2);
+        let app = Application::from_config(&config, &defaults, &queue).unwrap();
+        assert_eq!((app.activity_timeout, app.pong_timeout), (5, 2));
+    }
 }
@@ -0,0 +1,314 @@
+use axum::body::Bytes;
+use axum::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use axum::http::{Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+/// Backend that decides whether a socket may join a private or presence channel.
+///
+/// Requests are posted to `url`, or to `path` over the Unix socket `socket`, as
+/// `socket_id=..&channel_name=..` forms carrying the headers and cookies of the
+/// original auth request. A 2xx answer approves; its JSON body may hold the
+/// `channel_data` of a presence subscription. A 5xx answer counts as an outage
+/// and any other status as a denial.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthorizerConfig {
+    pub url: Option<String>,
+    pub socket: Option<PathBuf>,
+    /// Request path used with `socket`.
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// Seconds to wait for an answer.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+impl AuthorizerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.url, &self.socket) {
+            (Some(url), None) if url.starts_with("http://") || url.starts_with("https://") => {}
+            (Some(_), None) => return Err("url must be an http:// or https:// URL".to_string()),
+            (None, Some(_)) if self.path.starts_with('/') => {}
+            (None, Some(_)) => return Err("path must start with /".to_string()),
+            _ => return Err("exactly one of url and socket must be set".to_string()),
+        }
+        if self.timeout == 0 {
+            return Err("timeout must be at least 1 second".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthorizerError {
+    #[error("Authorizer denied the subscription with {0}")]
+    Denied(StatusCode),
+    #[error("Authorizer is unavailable: {0}")]
+    Unavailable(String),
+    #[error("Authorizer sent an invalid response: {0}")]
+    InvalidResponse(String),
+}
+
+/// Headers that describe the original connection or body rather than the user.
+const SKIPPED_HEADERS: [HeaderName; 8] = [
+    header::HOST,
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+    header::TE,
+    header::ACCEPT_ENCODING,
+];
+
+#[derive(Deserialize)]
+struct AuthorizerResponse {
+    #[serde(default)]
+    channel_data: Option<Value>,
+}
+
+/// Asks the backend whether `socket_id` may subscribe to `channel_name`.
+///
+/// Returns the `channel_data` the backend sent along with its approval.
+pub async fn authorize(
+    config: &AuthorizerConfig,
+    headers: &HeaderMap,
+    socket_id: &str,
+    channel_name: &str,
+) -> Result<Option<String>, AuthorizerError> {
+    let body =
+        serde_urlencoded::to_string([("socket_id", socket_id), ("channel_name", channel_name)])
+            .map_err(|e| AuthorizerError::Unavailable(e.to_string()))?;
+    let mut forwarded = headers.clone();
+    for name in &SKIPPED_HEADERS {
+        forwarded.remove(name);
+    }
+    forwarded.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
+    );
+
+    let timeout = Duration::from_secs(config.timeout);
+    let request = async {
+        match (&config.url, &config.socket) {
+            (_, Some(socket)) => post_unix(socket, &config.path, forwarded, body).await,
+            (Some(url), None) => post_http(url, forwarded, body).await,
+            (None, None) => Err("no url or socket configured".to_string()),
+        }
+    };
+    let (status, response) = tokio::time::timeout(timeout, request)
+        .await
+        .map_err(|_| AuthorizerError::Unavailable("timed out".to_string()))?
+        .map_err(AuthorizerError::Unavailable)?;
+    // A backend that fails is down, not refusing the subscription
+    if status.is_server_error() {
+        return Err(AuthorizerError::Unavailable(format!(
+            "answered with {}",
+            status
+        )));
+    }
+    if !status.is_success() {
+        return Err(AuthorizerError::Denied(status));
+    }
+    if response.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    let response: AuthorizerResponse = serde_json::from_slice(&response)
+        .map_err(|e| AuthorizerError::InvalidResponse(e.to_string()))?;
+    Ok(match response.channel_data {
+        None | Some(Value::Null) => None,
+        Some(Value::String(channel_data)) => Some(channel_data),
+        Some(channel_data) => Some(channel_data.to_string()),
+    })
+}
+
+async fn post_http(
+    url: &str,
+    headers: HeaderMap,
+    body: String,
+) -> Result<(StatusCode, Bytes), String> {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    let response = CLIENT
+        .get_or_init(reqwest::Client::new)
+        .post(url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response.bytes().await.map_err(|e| e.to_string())?;
+    Ok((status, body))
+}
+
+async fn post_unix(
+    socket: &Path,
+    path: &str,
+    headers: HeaderMap,
+    body: String,
+) -> Result<(StatusCode, Bytes), String> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| format!("{}: {}", socket.display(), e))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::spawn(connection);
+
+    let mut request = Request::post(path)
+        .header(header::HOST, "localhost")
+        .body(Full::new(Bytes::from(body)))
+        .map_err(|e| e.to_string())?;
+    request.headers_mut().extend(headers);
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| e.to_string())?
+        .to_bytes();
+    Ok((status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixListener;
+    use tokio::task::JoinHandle;
+
+    fn config(socket: PathBuf) -> AuthorizerConfig {
+        AuthorizerConfig {
+            url: None,
+            socket: Some(socket),
+            path: "/broadcasting/auth".to_string(),
+            timeout: 1,
+        }
+    }
+
+    /// Serves a single request on a fresh Unix socket with the raw HTTP
+    /// `response`, or never answers when it is `None`. Yields the request.
+    fn backend(name: &str, response: Option<&'static str>) -> (PathBuf, JoinHandle<String>) {
+        let socket = std::env::temp_dir().join(format!(
+            "sockudo-authorizer-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length: "))
+                        .map_or(0, |length| length.parse().unwrap());
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            match response {
+                Some(response) => stream.write_all(response.as_bytes()).await.unwrap(),
+                None => tokio::time::sleep(Duration::from_secs(5)).await,
+            }
+            String::from_utf8(request).unwrap()
+        });
+        (socket, server)
+    }
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("session=abc"));
+        headers.insert(
+            header::HOST,
+            HeaderValue::from_static("sockudo.example.com"),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn approval_passes_back_channel_data() {
+        let (socket, server) = backend(
+            "approve",
+            Some(concat!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 56\r\n\r\n",
+                r#"{"channel_data":{"user_id":"7","user_info":{"n":"ann"}}}"#
+            )),
+        );
+        let channel_data = authorize(&config(socket.clone()), &headers(), "1.1", "presence-room")
+            .await
+            .unwrap();
+        let channel_data: Value = serde_json::from_str(&channel_data.unwrap()).unwrap();
+        assert_eq!(channel_data["user_id"], "7");
+        assert_eq!(channel_data["user_info"]["n"], "ann");
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /broadcasting/auth HTTP/1.1\r\n"));
+        assert!(request.contains("cookie: session=abc\r\n"));
+        assert!(!request.contains("sockudo.example.com"));
+        assert!(request.ends_with("socket_id=1.1&channel_name=presence-room"));
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[tokio::test]
+    async fn client_errors_deny() {
+        let (socket, _server) = backend(
+            "deny",
+            Some("HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n"),
+        );
+        let denied = authorize(&config(socket.clone()), &headers(), "1.1", "private-room").await;
+        assert!(matches!(
+            denied,
+            Err(AuthorizerError::Denied(StatusCode::FORBIDDEN))
+        ));
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_outages() {
+        let (socket, _server) = backend(
+            "down",
+            Some("HTTP/1.1 502 Bad Gateway\r\ncontent-length: 0\r\n\r\n"),
+        );
+        let down = authorize(&config(socket.clone()), &headers(), "1.1", "private-room").await;
+        assert!(matches!(down, Err(AuthorizerError::Unavailable(_))));
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[tokio::test]
+    async fn silent_backends_time_out() {
+        let (socket, _server) = backend("silent", None);
+        let timed_out = authorize(&config(socket.clone()), &headers(), "1.1", "private-room").await;
+        match timed_out {
+            Err(AuthorizerError::Unavailable(reason)) => assert_eq!(reason, "timed out"),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        let _ = std::fs::remove_file(socket);
+    }
+}
@@ -0,0 +1,386 @@
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, JwkSet, PublicKeyUse};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Lets clients authorize `pusher:subscribe` with a JWT in `auth` instead of
+/// an HMAC signature.
+///
+/// Tokens are verified with the HS256 `secret`, or with the keys of the local
+/// JWKS file `jwks`, and must carry an `exp`. Their `channels` claim lists the
+/// channels they allow, where `*` matches any run of characters. Presence
+/// members are the token's `sub` with its `user_info` claim. HMAC signatures
+/// stay accepted alongside tokens unless `required` is set. Channel rules are
+/// applied first either way, and a channel they allow needs no token.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtConfig {
+    pub secret: Option<String>,
+    pub jwks: Option<PathBuf>,
+    /// Required `iss` of the tokens.
+    pub issuer: Option<String>,
+    /// Required `aud` of the tokens.
+    pub audience: Option<String>,
+    /// Refuse HMAC signatures, so private and presence channels need a token
+    /// unless a channel rule allows the subscription.
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("Invalid token: {0}")]
+    Invalid(String),
+    #[error("Token does not allow channel {0}")]
+    ChannelNotAllowed(String),
+}
+
+#[derive(Deserialize)]
+struct ChannelClaims {
+    sub: Option<String>,
+    #[serde(default)]
+    channels: Vec<String>,
+    #[serde(default)]
+    user_info: Value,
+}
+
+/// What a verified token grants on one channel.
+pub struct ChannelGrant {
+    user_id: Option<String>,
+    user_info: Value,
+}
+
+impl ChannelGrant {
+    /// Presence `channel_data` for the token's subject, if it has one.
+    pub fn presence_channel_data(&self) -> Option<String> {
+        let user_id = self.user_id.as_ref()?;
+        Some(json!({ "user_id": user_id, "user_info": self.user_info }).to_string())
+    }
+}
+
+struct VerificationKey {
+    id: Option<String>,
+    key: DecodingKey,
+    algorithms: Vec<Algorithm>,
+}
+
+/// The keys of a [`JwtConfig`], loaded when the app is.
+pub struct JwtVerifier {
+    keys: Vec<VerificationKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+    required: bool,
+}
+
+impl JwtConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.secret, &self.jwks) {
+            (Some(secret), None) if secret.is_empty() => {
+                Err("secret must not be empty".to_string())
+            }
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err("exactly one of secret and jwks must be set".to_string()),
+        }
+    }
+}
+
+impl JwtVerifier {
+    pub fn load(config: &JwtConfig) -> Result<Self, String> {
+        config.validate()?;
+        let keys = match (&config.secret, &config.jwks) {
+            (Some(secret), _) => vec![VerificationKey {
+                id: None,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+                algorithms: vec![Algorithm::HS256],
+            }],
+            (None, Some(path)) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                let jwks: JwkSet = serde_json::from_str(&contents)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                jwks_keys(jwks).map_err(|e| format!("{}: {}", path.display(), e))?
+            }
+            (None, None) => Vec::new(),
+        };
+        Ok(Self {
+            keys,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            required: config.required,
+        })
+    }
+
+    /// Whether subscriptions must present a token rather than an HMAC signature.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// Checks that `token` is valid and allows `channel_name`.
+    pub fn verify(&self, token: &str, channel_name: &str) -> Result<ChannelGrant, JwtError> {
+        let header = decode_header(token).map_err(|e| JwtError::Invalid(e.to_string()))?;
+        let key = match &header.kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|key| key.id.as_deref() == Some(kid.as_str()))
+                .or_else(|| self.only_key()),
+            None => self.only_key(),
+        }
+        .ok_or_else(|| JwtError::Invalid("no matching key".to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = key.algorithms.clone();
+        validation.set_required_spec_claims(&["exp"]);
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        let claims = decode::<ChannelClaims>(token, &key.key, &validation)
+            .map_err(|e| JwtError::Invalid(e.to_string()))?
+            .claims;
+
+        if !claims
+            .channels
+            .iter()
+            .any(|pattern| matches_pattern(pattern, channel_name))
+        {
+            return Err(JwtError::ChannelNotAllowed(channel_name.to_string()));
+        }
+        Ok(ChannelGrant {
+            user_id: claims.sub,
+            user_info: claims.user_info,
+        })
+    }
+
+    /// Tokens without a known `kid` can only be checked when there is one key.
+    fn only_key(&self) -> Option<&VerificationKey> {
+        match self.keys.as_slice() {
+            [key] => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// HMAC `auth` strings are `key:signature`; JWTs are three dot-separated parts.
+pub fn is_jwt(auth: &str) -> bool {
+    !auth.contains(':') && auth.split('.').count() == 3
+}
+
+/// Signing keys of the set, each limited to the algorithms of its key type, or
+/// to its `alg` when it names one.
+fn jwks_keys(jwks: JwkSet) -> Result<Vec<VerificationKey>, String> {
+    let keys = jwks
+        .keys
+        .iter()
+        .filter(|jwk| jwk.common.public_key_use != Some(PublicKeyUse::Encryption))
+        .map(|jwk| {
+            let kid = jwk.common.key_id.clone();
+            let describe = |e: &dyn std::fmt::Display| match &kid {
+                Some(kid) => format!("key {:?}: {}", kid, e),
+                None => format!("key without kid: {}", e),
+            };
+            let algorithms = match jwk.common.key_algorithm {
+                Some(algorithm) => {
+                    vec![Algorithm::from_str(&algorithm.to_string()).map_err(|e| describe(&e))?]
+                }
+                None => match &jwk.algorithm {
+                    AlgorithmParameters::RSA(_) => vec![
+                        Algorithm::RS256,
+                        Algorithm::RS384,
+                        Algorithm::RS512,
+                        Algorithm::PS256,
+                        Algorithm::PS384,
+                        Algorithm::PS512,
+                    ],
+                    AlgorithmParameters::EllipticCurve(params) => match params.curve {
+                        EllipticCurve::P256 => vec![Algorithm::ES256],
+                        EllipticCurve::P384 => vec![Algorithm::ES384],
+                        _ => return Err(describe(&"unsupported curve")),
+                    },
+                    AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+                    AlgorithmParameters::OctetKey(_) => {
+                        vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]
+                    }
+                },
+            };
+            let key = DecodingKey::from_jwk(jwk).map_err(|e| describe(&e))?;
+            Ok(VerificationKey {
+                id: kid.clone(),
+                key,
+                algorithms,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if keys.is_empty() {
+        return Err("no signing keys".to_string());
+    }
+    Ok(keys)
+}
+
+/// Matches `name` against a pattern in which `*` stands for any run of characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SECRET: &str = "identity-provider-secret";
+
+    /// Public half of an RSA key, as a JWKS would list it.
+    const RSA_JWKS: &str = r#"{"keys": [{
+        "kty": "RSA",
+        "kid": "rsa",
+        "use": "sig",
+        "n": "vUK2b83T_Dxq1UyYTT6TuWEGG4LtFy-E01SWZHiDZOuniIb_T5H7EqzVlRJJBEUfIh6KNuZ0_ZyPZVCHsruuSw",
+        "e": "AQAB"
+    }]}"#;
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn secret_verifier() -> JwtVerifier {
+        JwtVerifier::load(&JwtConfig {
+            secret: Some(SECRET.to_string()),
+            jwks: None,
+            issuer: None,
+            audience: None,
+            required: false,
+        })
+        .unwrap()
+    }
+
+    fn token(header: Header, claims: Value, secret: &[u8]) -> String {
+        encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    fn hs256(claims: Value) -> String {
+        token(Header::default(), claims, SECRET.as_bytes())
+    }
+
+    #[test]
+    fn accepts_a_valid_hs256_token() {
+        let token = hs256(json!({
+            "sub": "7",
+            "exp": now() + 60,
+            "channels": ["presence-team"],
+            "user_info": { "name": "Ann" },
+        }));
+        assert!(is_jwt(&token));
+        let grant = secret_verifier().verify(&token, "presence-team").unwrap();
+        let channel_data: Value =
+            serde_json::from_str(&grant.presence_channel_data().unwrap()).unwrap();
+        assert_eq!(
+            channel_data,
+            json!({ "user_id": "7", "user_info": { "name": "Ann" } })
+        );
+    }
+
+    #[test]
+    fn refuses_expired_tokens_and_tokens_without_exp() {
+        let expired = hs256(json!({ "exp": now() - 3600, "channels": ["private-a"] }));
+        assert!(matches!(
+            secret_verifier().verify(&expired, "private-a"),
+            Err(JwtError::Invalid(_))
+        ));
+        let forever = hs256(json!({ "channels": ["private-a"] }));
+        assert!(matches!(
+            secret_verifier().verify(&forever, "private-a"),
+            Err(JwtError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_tokens_signed_with_another_secret() {
+        let token = token(
+            Header::default(),
+            json!({ "exp": now() + 60, "channels": ["private-a"] }),
+            b"someone-else",
+        );
+        assert!(matches!(
+            secret_verifier().verify(&token, "private-a"),
+            Err(JwtError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_channels_the_token_does_not_list() {
+        let token = hs256(json!({ "exp": now() + 60, "channels": ["private-a"] }));
+        assert!(matches!(
+            secret_verifier().verify(&token, "private-b"),
+            Err(JwtError::ChannelNotAllowed(channel)) if channel == "private-b"
+        ));
+    }
+
+    #[test]
+    fn channel_patterns() {
+        let token = hs256(json!({
+            "exp": now() + 60,
+            "channels": ["private-orders.*", "presence-*-room-*"],
+        }));
+        let verifier = secret_verifier();
+        assert!(verifier.verify(&token, "private-orders.42").is_ok());
+        assert!(verifier.verify(&token, "presence-red-room-1").is_ok());
+        assert!(verifier.verify(&token, "private-orders-42").is_err());
+
+        assert!(matches_pattern("private-*", "private-"));
+        assert!(matches_pattern("*-chat", "private-a-chat"));
+        assert!(matches_pattern("private-*-*", "private-a-b"));
+        assert!(!matches_pattern("private-*-x", "private-x"));
+        assert!(!matches_pattern("private-a", "private-ab"));
+    }
+
+    #[test]
+    fn rsa_keys_refuse_hs_signed_tokens() {
+        let verifier = JwtVerifier {
+            keys: jwks_keys(serde_json::from_str(RSA_JWKS).unwrap()).unwrap(),
+            issuer: None,
+            audience: None,
+            required: false,
+        };
+        let claims = json!({ "exp": now() + 60, "channels": ["private-a"] });
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rsa".to_string());
+        // The public key is all an attacker needs to use it as an HMAC secret
+        let forged = token(header, claims, RSA_JWKS.as_bytes());
+        assert!(matches!(
+            verifier.verify(&forged, "private-a"),
+            Err(JwtError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn hmac_signatures_are_not_tokens() {
+        assert!(!is_jwt(
+            "278d425bdf160c739803:58df8b0c36d6982b82c3ecf6b4662e34fe8c25bba48f5369f135bf843651c3a4"
+        ));
+        assert!(!is_jwt("a.b"));
+    }
+}
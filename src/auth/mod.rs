@@ -0,0 +1,213 @@
+pub mod authorizer;
+pub mod jwt;
+pub mod rules;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Hex encoded HMAC-SHA256 of `payload` keyed with the application secret.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks a hex encoded HMAC-SHA256 signature in constant time.
+pub fn verify(secret: &str, payload: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn channel_string_to_sign(
+    socket_id: &str,
+    channel_name: &str,
+    channel_data: Option<&str>,
+) -> String {
+    match channel_data {
+        Some(data) => format!("{}:{}:{}", socket_id, channel_name, data),
+        None => format!("{}:{}", socket_id, channel_name),
+    }
+}
+
+/// Builds the `key:signature` auth string a client sends with `pusher:subscribe`.
+pub fn generate_auth_signature(
+    app_key: &str,
+    app_secret: &str,
+    socket_id: &str,
+    channel_name: &str,
+    channel_data: Option<&str>,
+) -> String {
+    let string_to_sign = channel_string_to_sign(socket_id, channel_name, channel_data);
+    format!("{}:{}", app_key, sign(app_secret, &string_to_sign))
+}
+
+/// Verifies the `key:signature` auth string of a private or presence subscription.
+pub fn verify_auth_signature(
+    app_key: &str,
+    app_secret: &str,
+    auth: &str,
+    socket_id: &str,
+    channel_name: &str,
+    channel_data: Option<&str>,
+) -> bool {
+    let string_to_sign = channel_string_to_sign(socket_id, channel_name, channel_data);
+    verify_key_signature(app_key, app_secret, auth, &string_to_sign)
+}
+
+/// Verifies the `key:signature` auth string of a `pusher:signin`, which signs
+/// `socket_id::user::user_data`.
+pub fn verify_user_signature(
+    app_key: &str,
+    app_secret: &str,
+    auth: &str,
+    socket_id: &str,
+    user_data: &str,
+) -> bool {
+    let string_to_sign = format!("{}::user::{}", socket_id, user_data);
+    verify_key_signature(app_key, app_secret, auth, &string_to_sign)
+}
+
+fn verify_key_signature(app_key: &str, app_secret: &str, auth: &str, string_to_sign: &str) -> bool {
+    let Some((key, signature)) = auth.split_once(':') else {
+        return false;
+    };
+    key == app_key && verify(app_secret, string_to_sign, signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The worked examples of https://pusher.com/docs/channels/library_auth_reference/auth-signatures/
+    const KEY: &str = "278d425bdf160c739803";
+    const SECRET: &str = "7ad3773142a6692b25b8";
+    const SOCKET_ID: &str = "1234.1234";
+    const PRIVATE_AUTH: &str =
+        "278d425bdf160c739803:58df8b0c36d6982b82c3ecf6b4662e34fe8c25bba48f5369f135bf843651c3a4";
+    const PRESENCE_DATA: &str = r#"{"user_id":10,"user_info":{"name":"Mr. Channels"}}"#;
+    const PRESENCE_AUTH: &str =
+        "278d425bdf160c739803:31935e7d86dba64c2a90aed31fdc61869f9b22ba9d8863bba239c03ca481bc80";
+
+    fn verify_private(auth: &str) -> bool {
+        verify_auth_signature(KEY, SECRET, auth, SOCKET_ID, "private-foobar", None)
+    }
+
+    #[test]
+    fn private_channel_signature() {
+        assert_eq!(
+            generate_auth_signature(KEY, SECRET, SOCKET_ID, "private-foobar", None),
+            PRIVATE_AUTH
+        );
+        assert!(verify_private(PRIVATE_AUTH));
+        assert!(!verify_auth_signature(
+            KEY,
+            SECRET,
+            PRIVATE_AUTH,
+            "1234.1235",
+            "private-foobar",
+            None
+        ));
+        assert!(!verify_auth_signature(
+            KEY,
+            SECRET,
+            PRIVATE_AUTH,
+            SOCKET_ID,
+            "private-foobaz",
+            None
+        ));
+    }
+
+    #[test]
+    fn presence_channel_signature_covers_channel_data() {
+        assert_eq!(
+            generate_auth_signature(
+                KEY,
+                SECRET,
+                SOCKET_ID,
+                "presence-foobar",
+                Some(PRESENCE_DATA)
+            ),
+            PRESENCE_AUTH
+        );
+        assert!(verify_auth_signature(
+            KEY,
+            SECRET,
+            PRESENCE_AUTH,
+            SOCKET_ID,
+            "presence-foobar",
+            Some(PRESENCE_DATA)
+        ));
+        assert!(!verify_auth_signature(
+            KEY,
+            SECRET,
+            PRESENCE_AUTH,
+            SOCKET_ID,
+            "presence-foobar",
+            Some(r#"{"user_id":11,"user_info":{"name":"Mr. Channels"}}"#)
+        ));
+        assert!(!verify_auth_signature(
+            KEY,
+            SECRET,
+            PRESENCE_AUTH,
+            SOCKET_ID,
+            "presence-foobar",
+            None
+        ));
+    }
+
+    #[test]
+    fn user_signature() {
+        let user_data = r#"{"id":"12345"}"#;
+        let auth = format!(
+            "{}:{}",
+            KEY,
+            sign(SECRET, &format!("{}::user::{}", SOCKET_ID, user_data))
+        );
+        assert!(verify_user_signature(
+            KEY, SECRET, &auth, SOCKET_ID, user_data
+        ));
+        assert!(!verify_user_signature(
+            KEY,
+            SECRET,
+            &auth,
+            SOCKET_ID,
+            r#"{"id":"54321"}"#
+        ));
+        assert!(!verify_user_signature(
+            KEY,
+            SECRET,
+            PRIVATE_AUTH,
+            SOCKET_ID,
+            user_data
+        ));
+    }
+
+    #[test]
+    fn refuses_another_apps_key() {
+        let signature = PRIVATE_AUTH.split_once(':').unwrap().1;
+        assert!(!verify_private(&format!(
+            "278d425bdf160c739804:{}",
+            signature
+        )));
+        assert!(!verify_private(&format!(":{}", signature)));
+        assert!(!verify_private(signature));
+    }
+
+    #[test]
+    fn refuses_malformed_signatures() {
+        assert!(!verify_private("278d425bdf160c739803:not-hex"));
+        assert!(!verify_private(&PRIVATE_AUTH.replace('5', "z")));
+        assert!(!verify_private(&PRIVATE_AUTH[..PRIVATE_AUTH.len() - 1]));
+        assert!(!verify_private(&PRIVATE_AUTH[..PRIVATE_AUTH.len() - 2]));
+        assert!(!verify_private("278d425bdf160c739803:"));
+        assert!(!verify(SECRET, "1234.1234:private-foobar", "zz"));
+    }
+}
@@ -0,0 +1,668 @@
+use crate::connection::Connection;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Channel decisions a connection keeps before its cache is started over.
+const DECISION_CACHE_SIZE: usize = 1024;
+
+/// Source of the versions that tie cached decisions to the rules that made them.
+static NEXT_RULES_VERSION: AtomicU64 = AtomicU64::new(1);
+
+/// Channel authorization rules read from a TOML file, in the spirit of
+/// Laravel's `routes/channels.php`:
+///
+/// ```toml
+/// [[rules]]
+/// channel = "private-orders.{userId}"
+/// when = ["userId == auth.user_id"]
+///
+/// [[rules]]
+/// channel = "presence-team.{team}"
+/// when = ["team in auth.user_data.user_info.teams", "auth.user_data.banned != true"]
+/// ```
+///
+/// The first rule whose pattern matches a channel decides it: the signed-in user
+/// may join when every condition holds. `{name}` placeholders match one
+/// dot-free segment of the channel name. Conditions compare two operands with
+/// `==`, `!=`, `in` or `not in`, where an operand is a placeholder,
+/// `auth.user_id`, a path into `auth.user_data`, a quoted string, a number,
+/// `true`, `false` or a `[...]` list of those.
+#[derive(Debug)]
+pub struct ChannelRules {
+    /// Distinguishes these rules from the ones an app reload replaces.
+    version: u64,
+    rules: Vec<ChannelRule>,
+}
+
+/// Decisions a connection remembers, by channel name, for the signed-in user
+/// and one version of the rules; a reload that changes the rules voids them.
+#[derive(Default)]
+pub struct RuleDecisions {
+    version: u64,
+    decisions: HashMap<String, Option<bool>>,
+}
+
+impl RuleDecisions {
+    pub fn clear(&mut self) {
+        self.decisions.clear();
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RuleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    channel: String,
+    #[serde(default)]
+    when: Vec<String>,
+}
+
+#[derive(Debug)]
+struct ChannelRule {
+    pattern: Vec<Part>,
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+#[derive(Debug)]
+struct Condition {
+    left: Operand,
+    operator: Operator,
+    right: Operand,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Equal,
+    NotEqual,
+    In,
+    NotIn,
+}
+
+#[derive(Debug)]
+enum Operand {
+    Value(Value),
+    Placeholder(String),
+    UserId,
+    /// Path into the user data; empty for the whole object.
+    UserData(Vec<String>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Word(String),
+    Equal,
+    NotEqual,
+    Open,
+    Close,
+    Comma,
+}
+
+/// The signed-in user a rule is evaluated against.
+pub struct RuleUser<'a> {
+    pub id: &'a str,
+    pub data: &'a Value,
+}
+
+impl ChannelRules {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let file: RulesFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                ChannelRule::parse(entry).map_err(|e| format!("rules[{}]: {}", index, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            version: NEXT_RULES_VERSION.fetch_add(1, Ordering::Relaxed),
+            rules,
+        })
+    }
+
+    /// Decides whether `user` may join `channel_name`, or returns `None` when no
+    /// rule covers the channel. Covered channels are refused to anonymous sockets.
+    pub fn evaluate(&self, channel_name: &str, user: Option<&RuleUser>) -> Option<bool> {
+        self.rules.iter().find_map(|rule| {
+            let mut bindings = Vec::new();
+            if !match_parts(&rule.pattern, channel_name, &mut bindings) {
+                return None;
+            }
+            Some(user.is_some_and(|user| {
+                rule.conditions
+                    .iter()
+                    .all(|condition| condition.holds(&bindings, user))
+            }))
+        })
+    }
+
+    /// Like [`evaluate`](Self::evaluate) for the user `connection` signed in as,
+    /// remembering the decision until the connection signs in again or the
+    /// rules are reloaded.
+    pub async fn evaluate_for(&self, channel_name: &str, connection: &Connection) -> Option<bool> {
+        {
+            let cache = connection.rule_decisions.lock().await;
+            if cache.version == self.version {
+                if let Some(decision) = cache.decisions.get(channel_name) {
+                    return *decision;
+                }
+            }
+        }
+        let user_id = connection.user_id.lock().await.clone();
+        let user_data = connection.user_data.lock().await.clone();
+        let user = match (&user_id, &user_data) {
+            (Some(id), Some(data)) => Some(RuleUser { id, data }),
+            _ => None,
+        };
+        let decision = self.evaluate(channel_name, user.as_ref());
+
+        let mut cache = connection.rule_decisions.lock().await;
+        if cache.version != self.version || cache.decisions.len() >= DECISION_CACHE_SIZE {
+            cache.decisions.clear();
+            cache.version = self.version;
+        }
+        cache.decisions.insert(channel_name.to_string(), decision);
+        decision
+    }
+}
+
+/// Presence `channel_data` of the user `connection` signed in as, taking the
+/// `user_info` of its user data.
+pub async fn presence_channel_data(connection: &Connection) -> Option<String> {
+    let user_id = connection.user_id.lock().await.clone()?;
+    let user_info = connection
+        .user_data
+        .lock()
+        .await
+        .as_ref()
+        .and_then(|data| data.get("user_info").cloned())
+        .unwrap_or(Value::Null);
+    Some(json!({ "user_id": user_id, "user_info": user_info }).to_string())
+}
+
+impl ChannelRule {
+    fn parse(entry: RuleEntry) -> Result<Self, String> {
+        if !entry.channel.starts_with("private-") && !entry.channel.starts_with("presence-") {
+            return Err(format!(
+                "{:?} is not a private- or presence- channel",
+                entry.channel
+            ));
+        }
+        let pattern = parse_pattern(&entry.channel)?;
+        let placeholders: HashSet<&str> = pattern
+            .iter()
+            .filter_map(|part| match part {
+                Part::Placeholder(name) => Some(name.as_str()),
+                Part::Literal(_) => None,
+            })
+            .collect();
+        let conditions = entry
+            .when
+            .iter()
+            .map(|condition| {
+                let parsed = Condition::parse(condition)
+                    .map_err(|e| format!("condition {:?}: {}", condition, e))?;
+                for operand in [&parsed.left, &parsed.right] {
+                    if let Operand::Placeholder(name) = operand {
+                        if !placeholders.contains(name.as_str()) {
+                            return Err(format!(
+                                "condition {:?}: {{{}}} is not in the channel pattern",
+                                condition, name
+                            ));
+                        }
+                    }
+                }
+                Ok(parsed)
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            pattern,
+            conditions,
+        })
+    }
+}
+
+fn parse_pattern(channel: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut rest = channel;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("unclosed placeholder in {:?}", channel))?;
+        if start > 0 {
+            parts.push(Part::Literal(rest[..start].to_string()));
+        } else if matches!(parts.last(), Some(Part::Placeholder(_))) {
+            return Err(format!("adjacent placeholders in {:?}", channel));
+        }
+        let name = &rest[start + 1..end];
+        if !is_identifier(name) || name == "auth" {
+            return Err(format!("invalid placeholder {{{}}} in {:?}", name, channel));
+        }
+        let duplicate = parts
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder(existing) if existing == name));
+        if duplicate {
+            return Err(format!("placeholder {{{}}} is used twice", name));
+        }
+        parts.push(Part::Placeholder(name.to_string()));
+        rest = &rest[end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(format!("unopened placeholder in {:?}", channel));
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Matches `name` against the pattern, collecting the placeholder values.
+fn match_parts(parts: &[Part], name: &str, bindings: &mut Vec<(String, String)>) -> bool {
+    match parts.split_first() {
+        None => name.is_empty(),
+        Some((Part::Literal(literal), rest)) => name
+            .strip_prefix(literal.as_str())
+            .is_some_and(|remaining| match_parts(rest, remaining, bindings)),
+        Some((Part::Placeholder(placeholder), rest)) => {
+            let segment = name.find('.').unwrap_or(name.len());
+            for end in (1..=segment).rev() {
+                if !name.is_char_boundary(end) {
+                    continue;
+                }
+                bindings.push((placeholder.clone(), name[..end].to_string()));
+                if match_parts(rest, &name[end..], bindings) {
+                    return true;
+                }
+                bindings.pop();
+            }
+            false
+        }
+    }
+}
+
+impl Condition {
+    fn parse(condition: &str) -> Result<Self, String> {
+        let mut tokens = tokenize(condition)?.into_iter().peekable();
+        let left = Operand::parse(&mut tokens)?;
+        let operator = match tokens.next() {
+            Some(Token::Equal) => Operator::Equal,
+            Some(Token::NotEqual) => Operator::NotEqual,
+            Some(Token::Word(word)) if word == "in" => Operator::In,
+            Some(Token::Word(word)) if word == "not" => match tokens.next() {
+                Some(Token::Word(word)) if word == "in" => Operator::NotIn,
+                _ => return Err("expected `in` after `not`".to_string()),
+            },
+            _ => return Err("expected ==, !=, in or not in".to_string()),
+        };
+        let right = Operand::parse(&mut tokens)?;
+        if tokens.next().is_some() {
+            return Err("unexpected input after the condition".to_string());
+        }
+        Ok(Self {
+            left,
+            operator,
+            right,
+        })
+    }
+
+    /// User data the user does not have is `null`; membership in anything but
+    /// a list never holds, negated or not.
+    fn holds(&self, bindings: &[(String, String)], user: &RuleUser) -> bool {
+        let left = self.left.resolve(bindings, user);
+        let right = self.right.resolve(bindings, user);
+        let contains = || match &right {
+            Value::Array(items) => Some(items.iter().any(|item| same(&left, item))),
+            _ => None,
+        };
+        match self.operator {
+            Operator::Equal => same(&left, &right),
+            Operator::NotEqual => !same(&left, &right),
+            Operator::In => contains() == Some(true),
+            Operator::NotIn => contains() == Some(false),
+        }
+    }
+}
+
+/// Compares scalars by their text, so that a placeholder or user id matches a
+/// numeric id in the user data.
+fn same(left: &Value, right: &Value) -> bool {
+    match (scalar_text(left), scalar_text(right)) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+impl Operand {
+    fn parse(tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>) -> Result<Self, String> {
+        match tokens.next() {
+            Some(Token::Open) => {
+                let mut items = Vec::new();
+                if tokens.peek() == Some(&Token::Close) {
+                    tokens.next();
+                    return Ok(Operand::Value(Value::Array(items)));
+                }
+                loop {
+                    items.push(literal(tokens.next())?);
+                    match tokens.next() {
+                        Some(Token::Comma) => continue,
+                        Some(Token::Close) => break,
+                        _ => return Err("expected , or ] in list".to_string()),
+                    }
+                }
+                Ok(Operand::Value(Value::Array(items)))
+            }
+            Some(Token::Word(word)) if word == "auth.user_id" => Ok(Operand::UserId),
+            Some(Token::Word(word)) if word == "auth.user_data" => Ok(Operand::UserData(vec![])),
+            Some(Token::Word(word)) if word.starts_with("auth.user_data.") => {
+                let path: Vec<String> = word["auth.user_data.".len()..]
+                    .split('.')
+                    .map(str::to_string)
+                    .collect();
+                if path.iter().any(String::is_empty) {
+                    return Err(format!("invalid path {}", word));
+                }
+                Ok(Operand::UserData(path))
+            }
+            Some(Token::Word(word)) if word == "auth" || word.starts_with("auth.") => Err(format!(
+                "unknown {}, expected auth.user_id or auth.user_data",
+                word
+            )),
+            Some(Token::Word(word)) if is_identifier(&word) && !is_keyword(&word) => {
+                Ok(Operand::Placeholder(word))
+            }
+            token => literal(token).map(Operand::Value),
+        }
+    }
+
+    fn resolve(&self, bindings: &[(String, String)], user: &RuleUser) -> Value {
+        match self {
+            Operand::Value(value) => value.clone(),
+            Operand::Placeholder(name) => bindings
+                .iter()
+                .find(|(placeholder, _)| placeholder == name)
+                .map(|(_, value)| Value::String(value.clone()))
+                .unwrap_or(Value::Null),
+            Operand::UserId => Value::String(user.id.to_string()),
+            Operand::UserData(path) => path
+                .iter()
+                .try_fold(user.data, |value, key| value.get(key))
+                .cloned()
+                .unwrap_or(Value::Null),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    matches!(word, "in" | "not" | "true" | "false")
+}
+
+fn literal(token: Option<Token>) -> Result<Value, String> {
+    match token {
+        Some(Token::Str(text)) => Ok(Value::String(text)),
+        Some(Token::Word(word)) if word == "true" => Ok(Value::Bool(true)),
+        Some(Token::Word(word)) if word == "false" => Ok(Value::Bool(false)),
+        Some(Token::Word(word)) => serde_json::from_str::<serde_json::Number>(&word)
+            .map(Value::Number)
+            .map_err(|_| format!("unexpected {}", word)),
+        Some(token) => Err(format!("unexpected {:?}", token)),
+        None => Err("unexpected end of condition".to_string()),
+    }
+}
+
+fn tokenize(condition: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = condition.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '[' => tokens.push(Token::Open),
+            ']' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '=' | '!' if chars.peek() == Some(&'=') => {
+                chars.next();
+                tokens.push(if c == '=' {
+                    Token::Equal
+                } else {
+                    Token::NotEqual
+                });
+            }
+            '\'' | '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(ch) => text.push(ch),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(text));
+            }
+            c if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !next.is_ascii_alphanumeric() && !matches!(next, '_' | '-' | '.') {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("unexpected {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{BackpressurePolicy, ClientInfo, OutboundQueue};
+    use std::sync::Arc;
+
+    fn rules(contents: &str) -> ChannelRules {
+        ChannelRules::parse(contents).unwrap()
+    }
+
+    fn pattern_error(channel: &str) -> String {
+        parse_pattern(channel).unwrap_err()
+    }
+
+    fn bindings(channel: &str, name: &str) -> Option<Vec<(String, String)>> {
+        let pattern = parse_pattern(channel).unwrap();
+        let mut bindings = Vec::new();
+        match_parts(&pattern, name, &mut bindings).then_some(bindings)
+    }
+
+    fn binding(placeholder: &str, value: &str) -> (String, String) {
+        (placeholder.to_string(), value.to_string())
+    }
+
+    fn holds(condition: &str, data: Value) -> bool {
+        let user = RuleUser {
+            id: "7",
+            data: &data,
+        };
+        Condition::parse(condition).unwrap().holds(&[], &user)
+    }
+
+    async fn signed_in(user_id: &str, user_data: Value) -> Arc<Connection> {
+        let outbound = OutboundQueue::new(16, BackpressurePolicy::DropNewest);
+        let connection = Connection::new("1.1".to_string(), ClientInfo::default(), outbound);
+        connection.set_user_id(user_id.to_string()).await;
+        connection.set_user_data(user_data).await;
+        connection
+    }
+
+    #[test]
+    fn pattern_errors() {
+        assert!(pattern_error("private-{user").contains("unclosed"));
+        assert!(pattern_error("private-user}").contains("unopened"));
+        assert!(pattern_error("private-{a}{b}").contains("adjacent"));
+        assert!(pattern_error("private-{a}.{a}").contains("used twice"));
+        assert!(pattern_error("private-{}").contains("invalid placeholder"));
+        assert!(pattern_error("private-{auth}").contains("invalid placeholder"));
+        assert!(ChannelRules::parse("[[rules]]\nchannel = \"orders.{id}\"").is_err());
+        assert!(ChannelRules::parse(
+            "[[rules]]\nchannel = \"private-orders.{id}\"\nwhen = [\"other == auth.user_id\"]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn placeholders_match_one_dot_free_segment() {
+        assert_eq!(
+            bindings("private-orders.{id}", "private-orders.42"),
+            Some(vec![binding("id", "42")])
+        );
+        assert_eq!(
+            bindings("private-orders.{id}", "private-orders.42.items"),
+            None
+        );
+        assert_eq!(bindings("private-orders.{id}", "private-orders."), None);
+        assert_eq!(
+            bindings("private-{team}.{user}", "private-red.7"),
+            Some(vec![binding("team", "red"), binding("user", "7")])
+        );
+    }
+
+    #[test]
+    fn placeholders_backtrack_to_fit_the_literal_after_them() {
+        assert_eq!(
+            bindings("private-{id}-chat", "private-a-b-chat"),
+            Some(vec![binding("id", "a-b")])
+        );
+        assert_eq!(
+            bindings("private-{first}-{second}", "private-a-b-c"),
+            Some(vec![binding("first", "a-b"), binding("second", "c")])
+        );
+        assert_eq!(bindings("private-{id}-chat", "private-a.b-chat"), None);
+    }
+
+    #[test]
+    fn missing_user_data_is_null() {
+        let data = json!({});
+        assert!(!holds("auth.user_data.banned == true", data.clone()));
+        assert!(holds("auth.user_data.banned != true", data.clone()));
+        assert!(!holds("'red' in auth.user_data.teams", data.clone()));
+        assert!(!holds("'red' not in auth.user_data.teams", data.clone()));
+        assert!(!holds(
+            "auth.user_data.team in ['red', 'blue']",
+            data.clone()
+        ));
+        assert!(holds("auth.user_data.team not in ['red', 'blue']", data));
+    }
+
+    #[test]
+    fn membership_needs_a_list() {
+        let data = json!({ "teams": ["red"], "team": "red" });
+        assert!(holds("'red' in auth.user_data.teams", data.clone()));
+        assert!(holds("'blue' not in auth.user_data.teams", data.clone()));
+        assert!(!holds("'red' in auth.user_data.team", data.clone()));
+        assert!(!holds("'blue' not in auth.user_data.team", data));
+    }
+
+    #[test]
+    fn numbers_and_strings_compare_by_text() {
+        assert!(same(&json!(42), &json!("42")));
+        assert!(same(&json!("true"), &json!(true)));
+        assert!(!same(&json!(42), &json!("042")));
+        assert!(!same(&Value::Null, &json!("null")));
+        assert!(holds(
+            "auth.user_id == auth.user_data.id",
+            json!({ "id": 7 })
+        ));
+        assert!(holds("7 in auth.user_data.ids", json!({ "ids": ["7"] })));
+    }
+
+    #[test]
+    fn covered_channels_are_refused_to_anonymous_sockets() {
+        let rules = rules("[[rules]]\nchannel = \"private-open\"");
+        let data = json!({});
+        let user = RuleUser {
+            id: "7",
+            data: &data,
+        };
+        assert_eq!(rules.evaluate("private-open", Some(&user)), Some(true));
+        assert_eq!(rules.evaluate("private-open", None), Some(false));
+        assert_eq!(rules.evaluate("private-other", None), None);
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules = rules(
+            r#"
+            [[rules]]
+            channel = "private-orders.{id}"
+            when = ["id == auth.user_id"]
+
+            [[rules]]
+            channel = "private-{any}"
+            "#,
+        );
+        let data = json!({});
+        let user = RuleUser {
+            id: "7",
+            data: &data,
+        };
+        assert_eq!(rules.evaluate("private-orders.7", Some(&user)), Some(true));
+        assert_eq!(rules.evaluate("private-orders.8", Some(&user)), Some(false));
+        assert_eq!(rules.evaluate("private-news", Some(&user)), Some(true));
+    }
+
+    #[tokio::test]
+    async fn reloaded_rules_do_not_reuse_cached_decisions() {
+        let connection = signed_in("7", json!({})).await;
+        let before = rules("[[rules]]\nchannel = \"private-news\"");
+        assert_eq!(
+            before.evaluate_for("private-news", &connection).await,
+            Some(true)
+        );
+
+        let after = rules(
+            "[[rules]]\nchannel = \"private-news\"\nwhen = [\"auth.user_data.editor == true\"]",
+        );
+        assert_eq!(
+            after.evaluate_for("private-news", &connection).await,
+            Some(false)
+        );
+    }
+}
@@ -1,9 +1,13 @@
-use super::{Channel, ChannelError, ChannelManager, ChannelType, PresenceChannel, PresenceUser};
+use super::{
+    CachedEvent, Channel, ChannelError, ChannelManager, ChannelType, Joined, Left, PresenceChannel,
+    PresenceUser, CACHED_EVENT_TTL,
+};
 use crate::connection::SafeConnection;
 use crate::log::Log;
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 
 struct PublicChannel {
@@ -11,9 +15,10 @@ struct PublicChannel {
     subscribers: RwLock<HashMap<String, SafeConnection>>,
 }
 
+/// Private channels behave like public ones once the subscription has been
+/// authorised, so they delegate everything but their type.
 struct PrivateChannel {
-    name: String,
-    subscribers: RwLock<HashMap<String, SafeConnection>>,
+    inner: PublicChannel,
 }
 
 struct PresenceChannelImpl {
@@ -36,40 +41,50 @@ impl Channel for PublicChannel {
         subscribers.keys().cloned().collect()
     }
 
-    async fn subscribe(&self, connection: &SafeConnection) -> Result<(), ChannelError> {
+    async fn subscribe(&self, connection: &SafeConnection) -> Result<Joined, ChannelError> {
         let mut subscribers = self.subscribers.write().await;
+        let occupied = subscribers.is_empty();
         subscribers.insert(connection.socket_id.clone(), Arc::clone(connection));
         Log::info(format!(
             "Subscribed {} to channel {}",
             connection.socket_id, self.name
         ));
-        Ok(())
+        Ok(Joined {
+            occupied,
+            new_member: false,
+        })
     }
 
-    async fn unsubscribe(&self, socket_id: &str) -> Result<(), ChannelError> {
-        self.subscribers.write().await.remove(socket_id);
-        Ok(())
+    async fn unsubscribe(&self, socket_id: &str) -> Result<Left, ChannelError> {
+        let mut subscribers = self.subscribers.write().await;
+        let removed = subscribers.remove(socket_id).is_some();
+        Ok(Left {
+            vacated: removed && subscribers.is_empty(),
+            removed_member: None,
+        })
     }
 
-    async fn broadcast(&self, message: String) -> Result<(), ChannelError> {
-        let subscribers = self.subscribers.write().await;
+    async fn broadcast(&self, message: String, except: Option<&str>) -> Result<(), ChannelError> {
+        let subscribers = self.subscribers.read().await;
         Log::info(format!(
             "Broadcasting message to {} subscribers: {}",
             subscribers.len(),
             message
         ));
         let now = chrono::Utc::now();
-        let cloned_message = message.clone();
 
         for connection in subscribers.keys() {
             Log::info(format!("Subscriber id {}", connection));
         }
 
-        tokio::join!(async {
-            for connection in subscribers.values() {
-                connection.send_message(cloned_message.clone()).await;
+        // Sends only enqueue, so a slow subscriber cannot hold up the others.
+        // A subscriber that is already closing is cleaned up by its own task.
+        for (socket_id, connection) in subscribers.iter() {
+            if Some(socket_id.as_str()) == except {
+                continue;
             }
-        },);
+            let _ = connection.send_message(message.clone());
+        }
         let elapsed = chrono::Utc::now().signed_duration_since(now);
         Log::info(format!("Broadcast completed in {:?}", elapsed));
         Ok(())
@@ -82,8 +97,9 @@ impl Channel for PublicChannel {
     ) -> Result<(), ChannelError> {
         let subscribers = self.subscribers.read().await;
         if let Some(connection) = subscribers.get(socket_id) {
-            connection.send_message(message).await;
-            Ok(())
+            connection
+                .send_message(message)
+                .map_err(|e| ChannelError::InternalError(e.to_string()))
         } else {
             Err(ChannelError::InternalError(
                 "Connection not found".to_string(),
@@ -100,27 +116,27 @@ impl Channel for PublicChannel {
 #[async_trait]
 impl Channel for PrivateChannel {
     fn name(&self) -> &str {
-        todo!()
+        self.inner.name()
     }
 
     fn channel_type(&self) -> ChannelType {
-        todo!()
+        ChannelType::Private
     }
 
     async fn subscribers(&self) -> Vec<String> {
-        todo!()
+        self.inner.subscribers().await
     }
 
-    async fn subscribe(&self, connection: &SafeConnection) -> Result<(), ChannelError> {
-        todo!()
+    async fn subscribe(&self, connection: &SafeConnection) -> Result<Joined, ChannelError> {
+        self.inner.subscribe(connection).await
     }
 
-    async fn unsubscribe(&self, socket_id: &str) -> Result<(), ChannelError> {
-        todo!()
+    async fn unsubscribe(&self, socket_id: &str) -> Result<Left, ChannelError> {
+        self.inner.unsubscribe(socket_id).await
     }
 
-    async fn broadcast(&self, message: String) -> Result<(), ChannelError> {
-        todo!()
+    async fn broadcast(&self, message: String, except: Option<&str>) -> Result<(), ChannelError> {
+        self.inner.broadcast(message, except).await
     }
 
     async fn send_to_connection(
@@ -128,14 +144,12 @@ impl Channel for PrivateChannel {
         socket_id: &str,
         message: String,
     ) -> Result<(), ChannelError> {
-        todo!()
+        self.inner.send_to_connection(socket_id, message).await
     }
 
     async fn subscriber_count(&self) -> Result<usize, ChannelError> {
-        todo!()
+        self.inner.subscriber_count().await
     }
-    // Implementation is identical to PublicChannel
-    // ...
 }
 
 #[async_trait]
@@ -149,23 +163,26 @@ impl Channel for PresenceChannelImpl {
     }
 
     async fn subscribers(&self) -> Vec<String> {
-        todo!()
+        let subscribers = self.subscribers.read().await;
+        subscribers.keys().cloned().collect()
     }
-    async fn subscribe(&self, connection: &SafeConnection) -> Result<(), ChannelError> {
-        // This should be called after add_presence_user
-        Ok(())
+
+    async fn subscribe(&self, _connection: &SafeConnection) -> Result<Joined, ChannelError> {
+        // Presence members join through add_presence_user
+        Ok(Joined::default())
     }
 
-    async fn unsubscribe(&self, socket_id: &str) -> Result<(), ChannelError> {
-        let mut subscribers = self.subscribers.write().await;
-        subscribers.remove(socket_id);
-        Ok(())
+    async fn unsubscribe(&self, socket_id: &str) -> Result<Left, ChannelError> {
+        self.remove_presence_user(socket_id).await
     }
 
-    async fn broadcast(&self, message: String) -> Result<(), ChannelError> {
+    async fn broadcast(&self, message: String, except: Option<&str>) -> Result<(), ChannelError> {
         let subscribers = self.subscribers.read().await;
-        for (connection, _) in subscribers.values() {
-            connection.send_message(message.clone()).await;
+        for (socket_id, (connection, _)) in subscribers.iter() {
+            if Some(socket_id.as_str()) == except {
+                continue;
+            }
+            let _ = connection.send_message(message.clone());
         }
         Ok(())
     }
@@ -178,8 +195,9 @@ impl Channel for PresenceChannelImpl {
         let subscribers = self.subscribers.read().await;
 
         if let Some((connection, _)) = subscribers.get(socket_id) {
-            connection.send_message(message).await;
-            Ok(())
+            connection
+                .send_message(message)
+                .map_err(|e| ChannelError::InternalError(e.to_string()))
         } else {
             Err(ChannelError::InternalError(
                 "Connection not found".to_string(),
@@ -191,6 +209,10 @@ impl Channel for PresenceChannelImpl {
         let subscribers = self.subscribers.read().await;
         Ok(subscribers.len())
     }
+
+    fn as_presence(&self) -> Option<&dyn PresenceChannel> {
+        Some(self)
+    }
 }
 
 #[async_trait]
@@ -199,36 +221,90 @@ impl PresenceChannel for PresenceChannelImpl {
         &self,
         connection: SafeConnection,
         user: PresenceUser,
-    ) -> Result<(), ChannelError> {
+    ) -> Result<Joined, ChannelError> {
         let mut subscribers = self.subscribers.write().await;
+        let occupied = subscribers.is_empty();
+        let new_member = !subscribers
+            .values()
+            .any(|(_, existing)| existing.user_id == user.user_id);
         subscribers.insert(connection.socket_id.clone(), (connection, user));
-        Ok(())
+        Ok(Joined {
+            occupied,
+            new_member,
+        })
     }
 
-    async fn remove_presence_user(&self, socket_id: &str) -> Result<(), ChannelError> {
+    async fn remove_presence_user(&self, socket_id: &str) -> Result<Left, ChannelError> {
         let mut subscribers = self.subscribers.write().await;
-        subscribers.remove(socket_id);
-        Ok(())
+        let Some((_, user)) = subscribers.remove(socket_id) else {
+            return Ok(Left::default());
+        };
+        let still_present = subscribers
+            .values()
+            .any(|(_, existing)| existing.user_id == user.user_id);
+        Ok(Left {
+            vacated: subscribers.is_empty(),
+            removed_member: (!still_present).then_some(user),
+        })
     }
 
     async fn get_presence_users(&self) -> Result<Vec<PresenceUser>, ChannelError> {
         let subscribers = self.subscribers.read().await;
-        Ok(subscribers.values().map(|(_, user)| user.clone()).collect())
+        let mut users: HashMap<&str, &PresenceUser> = HashMap::new();
+        for (_, user) in subscribers.values() {
+            users.entry(user.user_id.as_str()).or_insert(user);
+        }
+        Ok(users.into_values().cloned().collect())
+    }
+
+    async fn get_presence_user(
+        &self,
+        socket_id: &str,
+    ) -> Result<Option<PresenceUser>, ChannelError> {
+        let subscribers = self.subscribers.read().await;
+        Ok(subscribers.get(socket_id).map(|(_, user)| user.clone()))
     }
 }
 
 pub struct MemoryChannelManager {
     channels: RwLock<HashMap<String, Arc<dyn Channel>>>,
+    cached_events: RwLock<HashMap<String, CachedEvent>>,
 }
 
 impl MemoryChannelManager {
     pub fn new() -> Self {
         MemoryChannelManager {
             channels: RwLock::new(HashMap::new()),
+            cached_events: RwLock::new(HashMap::new()),
         }
     }
 }
 
+fn new_channel(name: String, channel_type: ChannelType) -> Arc<dyn Channel> {
+    match channel_type {
+        ChannelType::Public => Arc::new(PublicChannel {
+            name,
+            subscribers: RwLock::new(HashMap::new()),
+        }),
+        ChannelType::Private => Arc::new(PrivateChannel {
+            inner: PublicChannel {
+                name,
+                subscribers: RwLock::new(HashMap::new()),
+            },
+        }),
+        ChannelType::Presence => Arc::new(PresenceChannelImpl {
+            name,
+            subscribers: RwLock::new(HashMap::new()),
+        }),
+    }
+}
+
+impl Default for MemoryChannelManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[async_trait]
 impl ChannelManager for MemoryChannelManager {
     async fn create_channel(
@@ -237,25 +313,11 @@ impl ChannelManager for MemoryChannelManager {
         channel_type: ChannelType,
     ) -> Result<Arc<dyn Channel>, ChannelError> {
         let mut channels = self.channels.write().await;
-        if channels.contains_key(&name) {
-            return Ok(channels.get(&name).unwrap().clone());
+        if let Some(channel) = channels.get(&name) {
+            return Ok(channel.clone());
         }
-        let channel: Arc<dyn Channel> = match channel_type {
-            ChannelType::Public => Arc::new(PublicChannel {
-                name: name.clone(),
-                subscribers: RwLock::new(HashMap::new()),
-            }),
-            ChannelType::Private => Arc::new(PrivateChannel {
-                name: name.clone(),
-                subscribers: RwLock::new(HashMap::new()),
-            }),
-            ChannelType::Presence => Arc::new(PresenceChannelImpl {
-                name: name.clone(),
-                subscribers: RwLock::new(HashMap::new()),
-            }),
-        };
-
-        channels.insert(name.clone(), channel.clone());
+        let channel = new_channel(name.clone(), channel_type);
+        channels.insert(name, channel.clone());
         Ok(channel)
     }
 
@@ -270,8 +332,376 @@ impl ChannelManager for MemoryChannelManager {
         Ok(())
     }
 
+    // Subscribing and unsubscribing hold the channel map lock, so a channel found
+    // vacant cannot be joined again before it is removed.
+    async fn subscribe(
+        &self,
+        name: String,
+        channel_type: ChannelType,
+        connection: &SafeConnection,
+        member: Option<PresenceUser>,
+    ) -> Result<(Arc<dyn Channel>, Joined), ChannelError> {
+        let mut channels = self.channels.write().await;
+        let channel = channels
+            .entry(name)
+            .or_insert_with_key(|name| new_channel(name.clone(), channel_type))
+            .clone();
+        let joined = match (channel.as_presence(), member) {
+            (Some(presence), Some(member)) => {
+                presence
+                    .add_presence_user(connection.clone(), member)
+                    .await?
+            }
+            _ => channel.subscribe(connection).await?,
+        };
+        Ok((channel, joined))
+    }
+
+    async fn unsubscribe(
+        &self,
+        name: &str,
+        socket_id: &str,
+    ) -> Result<Option<(Arc<dyn Channel>, Left)>, ChannelError> {
+        let mut channels = self.channels.write().await;
+        let Some(channel) = channels.get(name).cloned() else {
+            return Ok(None);
+        };
+        let left = channel.unsubscribe(socket_id).await?;
+        if left.vacated {
+            channels.remove(name);
+        }
+        Ok(Some((channel, left)))
+    }
+
     async fn channel_exists(&self, name: &str) -> Result<bool, ChannelError> {
         let channels = self.channels.read().await;
         Ok(channels.contains_key(name))
     }
+
+    async fn get_channels(&self) -> Result<Vec<Arc<dyn Channel>>, ChannelError> {
+        let channels = self.channels.read().await;
+        Ok(channels.values().cloned().collect())
+    }
+
+    async fn cache_event(&self, name: &str, data: String) -> Result<(), ChannelError> {
+        let mut cached_events = self.cached_events.write().await;
+        let now = Instant::now();
+        cached_events.retain(|_, cached| cached.expires_at > now);
+        cached_events.insert(
+            name.to_string(),
+            CachedEvent {
+                data,
+                expires_at: now + CACHED_EVENT_TTL,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_cached_event(&self, name: &str) -> Result<Option<CachedEvent>, ChannelError> {
+        let cached_events = self.cached_events.read().await;
+        Ok(cached_events
+            .get(name)
+            .filter(|cached| cached.expires_at > Instant::now())
+            .cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::{
+        BackpressurePolicy, ClientInfo, Connection, OutboundMessage, OutboundQueue,
+    };
+    use serde_json::json;
+
+    type Outbound = Arc<OutboundQueue>;
+
+    /// Returns a connection together with the queue its writer task would drain.
+    fn test_connection(socket_id: &str) -> (SafeConnection, Outbound) {
+        let outbound = OutboundQueue::new(16, BackpressurePolicy::DropNewest);
+        (
+            Connection::new(
+                socket_id.to_string(),
+                ClientInfo::default(),
+                outbound.clone(),
+            ),
+            outbound,
+        )
+    }
+
+    fn next_message(outbound: &mut Outbound) -> String {
+        match outbound.try_next() {
+            Some(OutboundMessage::Text(text)) => text,
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    fn presence_user(user_id: &str) -> PresenceUser {
+        PresenceUser {
+            user_id: user_id.to_string(),
+            user_info: json!({ "name": user_id }),
+        }
+    }
+
+    async fn assert_channel_behaviour(channel: Arc<dyn Channel>) {
+        let (first, mut first_client) = test_connection("1.1");
+        let (second, mut second_client) = test_connection("2.2");
+
+        assert!(channel.subscribe(&first).await.unwrap().occupied);
+        assert!(!channel.subscribe(&second).await.unwrap().occupied);
+        assert_eq!(channel.subscriber_count().await.unwrap(), 2);
+        let mut subscribers = channel.subscribers().await;
+        subscribers.sort();
+        assert_eq!(subscribers, vec!["1.1", "2.2"]);
+
+        channel.broadcast("hello".to_string(), None).await.unwrap();
+        assert_eq!(next_message(&mut first_client), "hello");
+        assert_eq!(next_message(&mut second_client), "hello");
+
+        channel
+            .broadcast("not for 1.1".to_string(), Some("1.1"))
+            .await
+            .unwrap();
+        channel
+            .send_to_connection("1.1", "direct".to_string())
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut second_client), "not for 1.1");
+        assert_eq!(next_message(&mut first_client), "direct");
+        assert!(channel
+            .send_to_connection("3.3", "nobody".to_string())
+            .await
+            .is_err());
+
+        assert!(!channel.unsubscribe("1.1").await.unwrap().vacated);
+        assert_eq!(channel.subscriber_count().await.unwrap(), 1);
+        assert_eq!(channel.subscribers().await, vec!["2.2"]);
+        assert!(!channel.unsubscribe("1.1").await.unwrap().vacated);
+        assert!(channel.unsubscribe("2.2").await.unwrap().vacated);
+        assert!(channel.as_presence().is_none());
+    }
+
+    #[tokio::test]
+    async fn public_channel_through_channel_trait() {
+        let manager = MemoryChannelManager::new();
+        let channel = manager
+            .create_channel("chat".to_string(), ChannelType::Public)
+            .await
+            .unwrap();
+        assert_eq!(channel.name(), "chat");
+        assert_eq!(channel.channel_type(), ChannelType::Public);
+        assert_channel_behaviour(channel).await;
+    }
+
+    #[tokio::test]
+    async fn private_channel_through_channel_trait() {
+        let manager = MemoryChannelManager::new();
+        let channel = manager
+            .create_channel("private-chat".to_string(), ChannelType::Private)
+            .await
+            .unwrap();
+        assert_eq!(channel.name(), "private-chat");
+        assert_eq!(channel.channel_type(), ChannelType::Private);
+        assert_channel_behaviour(channel).await;
+    }
+
+    #[tokio::test]
+    async fn presence_channel_through_channel_trait() {
+        let manager = MemoryChannelManager::new();
+        let channel = manager
+            .create_channel("presence-room".to_string(), ChannelType::Presence)
+            .await
+            .unwrap();
+        assert_eq!(channel.name(), "presence-room");
+        assert_eq!(channel.channel_type(), ChannelType::Presence);
+        let presence = channel.as_presence().expect("presence channel");
+
+        let (first, mut first_client) = test_connection("1.1");
+        let (second, mut second_client) = test_connection("2.2");
+        let (third, _third_client) = test_connection("3.3");
+
+        let joined = presence
+            .add_presence_user(first, presence_user("alice"))
+            .await
+            .unwrap();
+        assert_eq!(
+            joined,
+            Joined {
+                occupied: true,
+                new_member: true
+            }
+        );
+        assert!(
+            !presence
+                .add_presence_user(second, presence_user("alice"))
+                .await
+                .unwrap()
+                .new_member
+        );
+        assert!(
+            presence
+                .add_presence_user(third, presence_user("bob"))
+                .await
+                .unwrap()
+                .new_member
+        );
+        assert_eq!(channel.subscriber_count().await.unwrap(), 3);
+        assert_eq!(presence.get_presence_users().await.unwrap().len(), 2);
+
+        channel
+            .broadcast("hello".to_string(), Some("3.3"))
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut first_client), "hello");
+        assert_eq!(next_message(&mut second_client), "hello");
+
+        channel
+            .send_to_connection("2.2", "direct".to_string())
+            .await
+            .unwrap();
+        assert_eq!(next_message(&mut second_client), "direct");
+
+        assert!(presence
+            .remove_presence_user("1.1")
+            .await
+            .unwrap()
+            .removed_member
+            .is_none());
+        let left = presence.remove_presence_user("2.2").await.unwrap();
+        assert_eq!(
+            left.removed_member.map(|user| user.user_id),
+            Some("alice".to_string())
+        );
+        assert!(!left.vacated);
+        assert_eq!(channel.subscribers().await, vec!["3.3"]);
+
+        let left = channel.unsubscribe("3.3").await.unwrap();
+        assert!(left.vacated);
+        assert_eq!(
+            left.removed_member.map(|user| user.user_id),
+            Some("bob".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn create_channel_returns_existing_channel() {
+        let manager = MemoryChannelManager::new();
+        let (connection, _client) = test_connection("1.1");
+        let channel = manager
+            .create_channel("private-chat".to_string(), ChannelType::Private)
+            .await
+            .unwrap();
+        channel.subscribe(&connection).await.unwrap();
+
+        let again = manager
+            .create_channel("private-chat".to_string(), ChannelType::Private)
+            .await
+            .unwrap();
+        assert_eq!(again.subscriber_count().await.unwrap(), 1);
+        assert!(manager.channel_exists("private-chat").await.unwrap());
+
+        manager.remove_channel("private-chat").await.unwrap();
+        assert!(manager.get_channel("private-chat").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn vacated_channels_are_removed() {
+        let manager = MemoryChannelManager::new();
+        let (first, _first_client) = test_connection("1.1");
+        let (second, _second_client) = test_connection("2.2");
+
+        let (_, joined) = manager
+            .subscribe("chat".to_string(), ChannelType::Public, &first, None)
+            .await
+            .unwrap();
+        assert!(joined.occupied);
+        let (_, joined) = manager
+            .subscribe("chat".to_string(), ChannelType::Public, &second, None)
+            .await
+            .unwrap();
+        assert!(!joined.occupied);
+
+        let (_, left) = manager.unsubscribe("chat", "1.1").await.unwrap().unwrap();
+        assert!(!left.vacated);
+        assert!(manager.channel_exists("chat").await.unwrap());
+        let (_, left) = manager.unsubscribe("chat", "2.2").await.unwrap().unwrap();
+        assert!(left.vacated);
+        assert!(!manager.channel_exists("chat").await.unwrap());
+        assert!(manager.unsubscribe("chat", "2.2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn concurrent_subscribers_see_one_transition() {
+        let manager = Arc::new(MemoryChannelManager::new());
+        let connections: Vec<_> = (0..16)
+            .map(|i| test_connection(&format!("{}.{}", i, i)))
+            .collect();
+
+        let joins = futures::future::join_all(connections.iter().map(|(connection, _)| {
+            let manager = manager.clone();
+            async move {
+                manager
+                    .subscribe(
+                        "presence-room".to_string(),
+                        ChannelType::Presence,
+                        connection,
+                        Some(presence_user("alice")),
+                    )
+                    .await
+                    .unwrap()
+                    .1
+            }
+        }))
+        .await;
+        assert_eq!(joins.iter().filter(|joined| joined.occupied).count(), 1);
+        assert_eq!(joins.iter().filter(|joined| joined.new_member).count(), 1);
+
+        let leaves = futures::future::join_all(connections.iter().map(|(connection, _)| {
+            let manager = manager.clone();
+            async move {
+                manager
+                    .unsubscribe("presence-room", &connection.socket_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .1
+            }
+        }))
+        .await;
+        assert_eq!(leaves.iter().filter(|left| left.vacated).count(), 1);
+        assert_eq!(
+            leaves
+                .iter()
+                .filter(|left| left.removed_member.is_some())
+                .count(),
+            1
+        );
+        assert!(!manager.channel_exists("presence-room").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn cache_keeps_the_data_of_the_last_event() {
+        let manager = MemoryChannelManager::new();
+        assert!(manager
+            .get_cached_event("cache-prices")
+            .await
+            .unwrap()
+            .is_none());
+
+        manager
+            .cache_event("cache-prices", r#"{"price":1}"#.to_string())
+            .await
+            .unwrap();
+        manager
+            .cache_event("cache-prices", r#"{"price":2}"#.to_string())
+            .await
+            .unwrap();
+        let cached = manager
+            .get_cached_event("cache-prices")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.data, r#"{"price":2}"#);
+        assert!(cached.expires_at > Instant::now());
+    }
 }
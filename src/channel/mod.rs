@@ -2,6 +2,7 @@ pub mod memory_channel_manager;
 
 use async_trait::async_trait;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use serde_json::Value;
 use crate::connection::SafeConnection;
 
@@ -12,29 +13,66 @@ pub enum ChannelType {
     Presence,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct PresenceUser {
     pub user_id: String,
     pub user_info: Value,
 }
 
+/// How long the last event of a cache channel is kept.
+pub const CACHED_EVENT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Data of the last event published to a cache channel.
+#[derive(Debug, Clone)]
+pub struct CachedEvent {
+    pub data: String,
+    pub expires_at: Instant,
+}
+
+/// What adding a socket changed about a channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Joined {
+    /// The channel had no subscribers before.
+    pub occupied: bool,
+    /// The socket's user had no other socket in the presence channel.
+    pub new_member: bool,
+}
+
+/// What removing a socket changed about a channel.
+#[derive(Debug, Clone, Default)]
+pub struct Left {
+    /// The socket was the channel's last subscriber.
+    pub vacated: bool,
+    /// The presence user whose last socket in the channel this was.
+    pub removed_member: Option<PresenceUser>,
+}
+
 #[async_trait]
 pub trait Channel: Send + Sync {
     fn name(&self) -> &str;
     fn channel_type(&self) -> ChannelType;
     async fn subscribers(&self) -> Vec<String>;
-    async fn subscribe(&self, connection: &SafeConnection) -> Result<(), ChannelError>;
-    async fn unsubscribe(&self, socket_id: &str) -> Result<(), ChannelError>;
-    async fn broadcast(&self, message: String) -> Result<(), ChannelError>;
+    async fn subscribe(&self, connection: &SafeConnection) -> Result<Joined, ChannelError>;
+    async fn unsubscribe(&self, socket_id: &str) -> Result<Left, ChannelError>;
+    /// Sends `message` to every subscriber except the socket `except`, if given.
+    async fn broadcast(&self, message: String, except: Option<&str>) -> Result<(), ChannelError>;
     async fn send_to_connection(&self, socket_id: &str, message: String) -> Result<(), ChannelError>;
     async fn subscriber_count(&self) -> Result<usize, ChannelError>;
+
+    fn as_presence(&self) -> Option<&dyn PresenceChannel> {
+        None
+    }
 }
 
 #[async_trait]
 pub trait PresenceChannel: Channel {
-    async fn add_presence_user(&self, connection: SafeConnection, user: PresenceUser) -> Result<(), ChannelError>;
-    async fn remove_presence_user(&self, socket_id: &str) -> Result<(), ChannelError>;
+    /// Reports a new member when this is the first socket of `user.user_id` in the channel.
+    async fn add_presence_user(&self, connection: SafeConnection, user: PresenceUser) -> Result<Joined, ChannelError>;
+    /// Reports the removed member once their last socket has left the channel.
+    async fn remove_presence_user(&self, socket_id: &str) -> Result<Left, ChannelError>;
+    /// Returns one entry per distinct `user_id`.
     async fn get_presence_users(&self) -> Result<Vec<PresenceUser>, ChannelError>;
+    async fn get_presence_user(&self, socket_id: &str) -> Result<Option<PresenceUser>, ChannelError>;
 }
 
 #[async_trait]
@@ -42,7 +80,22 @@ pub trait ChannelManager: Send + Sync {
     async fn create_channel(&self, name: String, channel_type: ChannelType) -> Result<Arc<dyn Channel>, ChannelError>;
     async fn get_channel(&self, name: &str) -> Result<Option<Arc<dyn Channel>>, ChannelError>;
     async fn remove_channel(&self, name: &str) -> Result<(), ChannelError>;
+    /// Adds the connection to the channel, creating the channel first if needed.
+    /// `member` is the user a presence subscription joins as.
+    async fn subscribe(
+        &self,
+        name: String,
+        channel_type: ChannelType,
+        connection: &SafeConnection,
+        member: Option<PresenceUser>,
+    ) -> Result<(Arc<dyn Channel>, Joined), ChannelError>;
+    /// Removes the socket from the channel and drops the channel once it is vacant.
+    /// Returns `None` when there is no such channel.
+    async fn unsubscribe(&self, name: &str, socket_id: &str) -> Result<Option<(Arc<dyn Channel>, Left)>, ChannelError>;
     async fn channel_exists(&self, name: &str) -> Result<bool, ChannelError>;
+    async fn get_channels(&self) -> Result<Vec<Arc<dyn Channel>>, ChannelError>;
+    async fn cache_event(&self, name: &str, data: String) -> Result<(), ChannelError>;
+    async fn get_cached_event(&self, name: &str) -> Result<Option<CachedEvent>, ChannelError>;
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -59,6 +112,33 @@ pub enum ChannelError {
     InternalError(String),
 }
 
+/// Channel names may only contain `[A-Za-z0-9_\-=@,.;#]` and be at most 164 characters long.
+pub fn validate_channel_name(name: &str) -> Result<(), ChannelError> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-=@,.;#".contains(c));
+    if name.is_empty() || name.len() > 164 || !valid_chars {
+        return Err(ChannelError::InvalidChannelName);
+    }
+    Ok(())
+}
+
+/// Prefix of the channel that events sent to a signed-in user arrive on.
+pub const SERVER_TO_USER_PREFIX: &str = "#server-to-user-";
+
+/// Returns the user a `#server-to-user-{id}` channel belongs to.
+pub fn server_to_user_id(name: &str) -> Option<&str> {
+    name.strip_prefix(SERVER_TO_USER_PREFIX)
+        .filter(|user_id| !user_id.is_empty())
+}
+
+/// Cache channels remember their last event for [`CACHED_EVENT_TTL`].
+pub fn is_cache_channel(name: &str) -> bool {
+    ["cache-", "private-cache-", "private-encrypted-cache-", "presence-cache-"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
 pub type SafeChannelManager = Arc<dyn ChannelManager>;
 
 pub fn create_channel_manager() -> SafeChannelManager {
@@ -0,0 +1,728 @@
+use crate::application::{DEFAULT_ACTIVITY_TIMEOUT, DEFAULT_PONG_TIMEOUT};
+use crate::auth::authorizer::AuthorizerConfig;
+use crate::auth::jwt::{JwtConfig, JwtVerifier};
+use crate::auth::rules::ChannelRules;
+use crate::connection::{BackpressurePolicy, OUTBOUND_QUEUE_SIZE};
+use crate::log::LogLevel;
+use crate::webhook::WebhookConfig;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Longest first retry delay of a webhook delivery, in seconds.
+pub const MAX_WEBHOOK_RETRY_DELAY: u64 = 3600;
+
+/// Config file read when `SOCKUDO_CONFIG` is not set.
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config file {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("Invalid value {value:?} for {var}: {message}")]
+    Env {
+        var: String,
+        value: String,
+        message: String,
+    },
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub log_level: LogLevel,
+    pub timeouts: TimeoutConfig,
+    pub app_manager: AppManagerConfig,
+    pub webhook_queue: WebhookQueueConfig,
+    /// Apps served by the `static` app manager.
+    pub apps: Vec<AppConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 6001,
+            log_level: LogLevel::Info,
+            timeouts: TimeoutConfig::default(),
+            app_manager: AppManagerConfig::default(),
+            webhook_queue: WebhookQueueConfig::default(),
+            apps: Vec::new(),
+        }
+    }
+}
+
+/// Timeouts, in seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Inactivity after which the server pings a client.
+    pub activity_timeout: u64,
+    /// How long a client has to answer a server ping.
+    pub pong_timeout: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            activity_timeout: DEFAULT_ACTIVITY_TIMEOUT,
+            pong_timeout: DEFAULT_PONG_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppManagerDriver {
+    /// The `[[apps]]` entries of this file.
+    #[default]
+    Static,
+    /// A JSON array of apps in `path`, reloaded when the file changes.
+    Json,
+    /// The `apps` table of the SQLite database at `path`.
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppManagerConfig {
+    pub driver: AppManagerDriver,
+    pub path: Option<PathBuf>,
+    /// Seconds app lookups are cached for; 0 disables the cache.
+    pub cache_ttl: u64,
+    /// Seconds between checks of the JSON file for changes.
+    pub watch_interval: u64,
+}
+
+impl Default for AppManagerConfig {
+    fn default() -> Self {
+        Self {
+            driver: AppManagerDriver::Static,
+            path: None,
+            cache_ttl: 0,
+            watch_interval: 5,
+        }
+    }
+}
+
+/// Persistence and retry policy of webhook deliveries, shared by all apps.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookQueueConfig {
+    /// SQLite database holding pending and dead-lettered deliveries.
+    pub path: PathBuf,
+    /// Deliveries in flight per app.
+    pub concurrency: usize,
+    /// Attempts before a delivery is dead-lettered.
+    pub max_attempts: u32,
+    /// Seconds before the first retry, doubled for every further one.
+    pub retry_delay: u64,
+    /// Consecutive failures that open an endpoint's circuit.
+    pub breaker_threshold: u32,
+    /// Seconds an open circuit waits before letting a trial delivery through.
+    pub breaker_cooldown: u64,
+}
+
+impl Default for WebhookQueueConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("webhooks.db"),
+            concurrency: 4,
+            max_attempts: 5,
+            retry_delay: 1,
+            breaker_threshold: 5,
+            breaker_cooldown: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppConfig {
+    pub id: String,
+    pub key: String,
+    pub secret: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(default = "enabled")]
+    pub enable_client_messages: bool,
+    #[serde(default)]
+    pub limits: AppLimits,
+    #[serde(default)]
+    pub timeouts: AppTimeouts,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Backend consulted by the auth endpoint.
+    #[serde(default)]
+    pub authorizer: Option<AuthorizerConfig>,
+    /// Lets the auth endpoint sign requests that neither the authorizer nor a
+    /// channel rule decides. Anyone can then join any private or presence
+    /// channel, so this is only meant for development.
+    #[serde(default)]
+    pub open_auth: bool,
+    /// TOML file of channel authorization rules, read when the app is loaded.
+    #[serde(default)]
+    pub channel_rules: Option<PathBuf>,
+    /// Accepts JWTs besides HMAC signatures when subscribing.
+    #[serde(default)]
+    pub jwt: Option<JwtConfig>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppLimits {
+    /// Maximum concurrent connections; unlimited when unset.
+    pub max_connections: Option<usize>,
+    /// Maximum number of frames queued per connection.
+    pub max_buffer_size: usize,
+    pub backpressure_policy: BackpressurePolicy,
+}
+
+/// Per-app overrides of the `[timeouts]` section, in seconds.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppTimeouts {
+    pub activity_timeout: Option<u64>,
+    pub pong_timeout: Option<u64>,
+}
+
+impl Default for AppLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_buffer_size: OUTBOUND_QUEUE_SIZE,
+            backpressure_policy: BackpressurePolicy::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the config file named by `SOCKUDO_CONFIG` (or `config.toml` when present),
+    /// applies `SOCKUDO_*` environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = match std::env::var("SOCKUDO_CONFIG") {
+            Ok(path) => Self::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Self::default(),
+        };
+        config.apply_env(|var| std::env::var(var).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Parses a TOML file, or JSON when the file has a `.json` extension.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let parsed = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&contents).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|message| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        })
+    }
+
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), ConfigError> {
+        if let Some(host) = env("SOCKUDO_HOST") {
+            self.host = host;
+        }
+        if let Some(port) = parse_env(&env, "SOCKUDO_PORT")? {
+            self.port = port;
+        }
+        if let Some(level) = env("SOCKUDO_LOG_LEVEL") {
+            self.log_level = match level.to_lowercase().as_str() {
+                "error" => LogLevel::Error,
+                "warn" | "warning" => LogLevel::Warn,
+                "info" => LogLevel::Info,
+                _ => {
+                    return Err(ConfigError::Env {
+                        var: "SOCKUDO_LOG_LEVEL".to_string(),
+                        value: level,
+                        message: "expected error, warn or info".to_string(),
+                    })
+                }
+            };
+        }
+        if let Some(timeout) = parse_env(&env, "SOCKUDO_ACTIVITY_TIMEOUT")? {
+            self.timeouts.activity_timeout = timeout;
+        }
+        if let Some(timeout) = parse_env(&env, "SOCKUDO_PONG_TIMEOUT")? {
+            self.timeouts.pong_timeout = timeout;
+        }
+        if let Some(driver) = env("SOCKUDO_APP_MANAGER") {
+            self.app_manager.driver = match driver.to_lowercase().as_str() {
+                "static" => AppManagerDriver::Static,
+                "json" => AppManagerDriver::Json,
+                "sqlite" => AppManagerDriver::Sqlite,
+                _ => {
+                    return Err(ConfigError::Env {
+                        var: "SOCKUDO_APP_MANAGER".to_string(),
+                        value: driver,
+                        message: "expected static, json or sqlite".to_string(),
+                    })
+                }
+            };
+        }
+        if let Some(path) = env("SOCKUDO_APP_MANAGER_PATH") {
+            self.app_manager.path = Some(PathBuf::from(path));
+        }
+        if let Some(ttl) = parse_env(&env, "SOCKUDO_APP_CACHE_TTL")? {
+            self.app_manager.cache_ttl = ttl;
+        }
+        if let Some(path) = env("SOCKUDO_WEBHOOK_QUEUE_PATH") {
+            self.webhook_queue.path = PathBuf::from(path);
+        }
+
+        // A single app can be defined or overridden without a config file
+        let default_app = (
+            env("SOCKUDO_DEFAULT_APP_ID"),
+            env("SOCKUDO_DEFAULT_APP_KEY"),
+            env("SOCKUDO_DEFAULT_APP_SECRET"),
+        );
+        match default_app {
+            (Some(id), Some(key), Some(secret)) => {
+                self.apps.retain(|app| app.id != id);
+                self.apps.push(AppConfig {
+                    id,
+                    key,
+                    secret,
+                    enabled: true,
+                    enable_client_messages: true,
+                    limits: AppLimits::default(),
+                    timeouts: AppTimeouts::default(),
+                    webhooks: Vec::new(),
+                    authorizer: None,
+                    open_auth: false,
+                    channel_rules: None,
+                    jwt: None,
+                });
+            }
+            (None, None, None) => {}
+            _ => {
+                return Err(ConfigError::Invalid(
+                    "SOCKUDO_DEFAULT_APP_ID, SOCKUDO_DEFAULT_APP_KEY and \
+                     SOCKUDO_DEFAULT_APP_SECRET must be set together"
+                        .to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.host.trim().is_empty() {
+            return Err(ConfigError::Invalid("host must not be empty".to_string()));
+        }
+        if self.port == 0 {
+            return Err(ConfigError::Invalid("port must not be 0".to_string()));
+        }
+        if self.timeouts.activity_timeout == 0 {
+            return Err(ConfigError::Invalid(
+                "timeouts.activity_timeout must be at least 1 second".to_string(),
+            ));
+        }
+        if self.timeouts.pong_timeout == 0 {
+            return Err(ConfigError::Invalid(
+                "timeouts.pong_timeout must be at least 1 second".to_string(),
+            ));
+        }
+        match self.app_manager.driver {
+            AppManagerDriver::Static if self.apps.is_empty() => {
+                return Err(ConfigError::Invalid(format!(
+                    "no apps configured; add an [[apps]] entry to {} or set \
+                     SOCKUDO_DEFAULT_APP_ID, SOCKUDO_DEFAULT_APP_KEY and SOCKUDO_DEFAULT_APP_SECRET",
+                    DEFAULT_CONFIG_PATH
+                )));
+            }
+            AppManagerDriver::Json | AppManagerDriver::Sqlite
+                if self.app_manager.path.is_none() =>
+            {
+                return Err(ConfigError::Invalid(
+                    "app_manager.path is required for the json and sqlite drivers".to_string(),
+                ));
+            }
+            _ => {}
+        }
+        if self.app_manager.watch_interval == 0 {
+            return Err(ConfigError::Invalid(
+                "app_manager.watch_interval must be at least 1 second".to_string(),
+            ));
+        }
+        let webhook_queue = &self.webhook_queue;
+        for (field, value) in [
+            ("concurrency", webhook_queue.concurrency as u64),
+            ("max_attempts", webhook_queue.max_attempts as u64),
+            ("breaker_threshold", webhook_queue.breaker_threshold as u64),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "webhook_queue.{} must be at least 1",
+                    field
+                )));
+            }
+        }
+        if webhook_queue.retry_delay > MAX_WEBHOOK_RETRY_DELAY {
+            return Err(ConfigError::Invalid(format!(
+                "webhook_queue.retry_delay must be at most {} seconds",
+                MAX_WEBHOOK_RETRY_DELAY
+            )));
+        }
+        validate_apps(&self.apps).map_err(ConfigError::Invalid)
+    }
+}
+
+/// Checks that every app is complete and that ids and keys are unique.
+pub fn validate_apps(apps: &[AppConfig]) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    let mut keys = HashSet::new();
+    for (index, app) in apps.iter().enumerate() {
+        validate_app(app).map_err(|message| format!("apps[{}].{}", index, message))?;
+        if !ids.insert(app.id.as_str()) {
+            return Err(format!(
+                "apps[{}].id {:?} is used by more than one app",
+                index, app.id
+            ));
+        }
+        if !keys.insert(app.key.as_str()) {
+            return Err(format!(
+                "apps[{}].key {:?} is used by more than one app",
+                index, app.key
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the settings of a single app, whichever backend it came from.
+pub fn validate_app(app: &AppConfig) -> Result<(), String> {
+    for (field, value) in [("id", &app.id), ("key", &app.key), ("secret", &app.secret)] {
+        if value.trim().is_empty() {
+            return Err(format!("{} must not be empty", field));
+        }
+    }
+    if app.limits.max_buffer_size == 0 {
+        return Err("limits.max_buffer_size must be at least 1".to_string());
+    }
+    for (field, timeout) in [
+        ("activity_timeout", app.timeouts.activity_timeout),
+        ("pong_timeout", app.timeouts.pong_timeout),
+    ] {
+        if timeout == Some(0) {
+            return Err(format!("timeouts.{} must be at least 1 second", field));
+        }
+    }
+    for (index, webhook) in app.webhooks.iter().enumerate() {
+        if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+            return Err(format!(
+                "webhooks[{}].url must be an http:// or https:// URL",
+                index
+            ));
+        }
+    }
+    if let Some(authorizer) = &app.authorizer {
+        authorizer
+            .validate()
+            .map_err(|message| format!("authorizer: {}", message))?;
+    }
+    if let Some(path) = &app.channel_rules {
+        ChannelRules::load(path).map_err(|message| format!("channel_rules: {}", message))?;
+    }
+    if let Some(jwt) = &app.jwt {
+        JwtVerifier::load(jwt).map_err(|message| format!("jwt: {}", message))?;
+    }
+    Ok(())
+}
+
+fn parse_env<T>(env: impl Fn(&str) -> Option<String>, var: &str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = env(var) else {
+        return Ok(None);
+    };
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e: T::Err| ConfigError::Env {
+            var: var.to_string(),
+            value,
+            message: e.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn app(id: &str, key: &str) -> AppConfig {
+        AppConfig {
+            id: id.to_string(),
+            key: key.to_string(),
+            secret: "secret".to_string(),
+            enabled: true,
+            enable_client_messages: true,
+            limits: AppLimits::default(),
+            timeouts: AppTimeouts::default(),
+            webhooks: Vec::new(),
+            authorizer: None,
+            open_auth: false,
+            channel_rules: None,
+            jwt: None,
+        }
+    }
+
+    fn config() -> ServerConfig {
+        ServerConfig {
+            apps: vec![app("1", "key")],
+            ..ServerConfig::default()
+        }
+    }
+
+    fn apply(config: &mut ServerConfig, vars: &[(&str, &str)]) -> Result<(), ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect();
+        config.apply_env(|var| vars.get(var).cloned())
+    }
+
+    fn invalid(config: ServerConfig) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    fn invalid_app(change: impl FnOnce(&mut AppConfig)) -> String {
+        let mut config = config();
+        change(&mut config.apps[0]);
+        invalid(config)
+    }
+
+    #[test]
+    fn env_overrides_the_file() {
+        let mut config = config();
+        apply(
+            &mut config,
+            &[
+                ("SOCKUDO_HOST", "127.0.0.1"),
+                ("SOCKUDO_PORT", "7001"),
+                ("SOCKUDO_LOG_LEVEL", "WARNING"),
+                ("SOCKUDO_ACTIVITY_TIMEOUT", "60"),
+                ("SOCKUDO_PONG_TIMEOUT", "10"),
+                ("SOCKUDO_APP_MANAGER", "sqlite"),
+                ("SOCKUDO_APP_MANAGER_PATH", "apps.db"),
+                ("SOCKUDO_APP_CACHE_TTL", "30"),
+                ("SOCKUDO_WEBHOOK_QUEUE_PATH", "queue.db"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.port, 7001);
+        assert!(matches!(config.log_level, LogLevel::Warn));
+        assert_eq!(config.timeouts.activity_timeout, 60);
+        assert_eq!(config.timeouts.pong_timeout, 10);
+        assert_eq!(config.app_manager.driver, AppManagerDriver::Sqlite);
+        assert_eq!(config.app_manager.path, Some(PathBuf::from("apps.db")));
+        assert_eq!(config.app_manager.cache_ttl, 30);
+        assert_eq!(config.webhook_queue.path, PathBuf::from("queue.db"));
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn env_default_app_replaces_the_app_with_its_id() {
+        let mut config = config();
+        config.apps.push(app("2", "other"));
+        apply(
+            &mut config,
+            &[
+                ("SOCKUDO_DEFAULT_APP_ID", "1"),
+                ("SOCKUDO_DEFAULT_APP_KEY", "new-key"),
+                ("SOCKUDO_DEFAULT_APP_SECRET", "new-secret"),
+            ],
+        )
+        .unwrap();
+        let apps: Vec<(&str, &str)> = config
+            .apps
+            .iter()
+            .map(|app| (app.id.as_str(), app.key.as_str()))
+            .collect();
+        assert_eq!(apps, vec![("2", "other"), ("1", "new-key")]);
+
+        let mut config = ServerConfig::default();
+        let error = apply(&mut config, &[("SOCKUDO_DEFAULT_APP_ID", "1")]).unwrap_err();
+        assert!(error.to_string().contains("must be set together"));
+    }
+
+    #[test]
+    fn bad_env_values_name_the_variable() {
+        for (var, value, message) in [
+            ("SOCKUDO_PORT", "http", "invalid digit"),
+            ("SOCKUDO_PORT", "70000", "too large"),
+            ("SOCKUDO_LOG_LEVEL", "debug", "expected error, warn or info"),
+            (
+                "SOCKUDO_APP_MANAGER",
+                "redis",
+                "expected static, json or sqlite",
+            ),
+            ("SOCKUDO_PONG_TIMEOUT", "-1", "invalid digit"),
+        ] {
+            match apply(&mut config(), &[(var, value)]) {
+                Err(ConfigError::Env {
+                    var: bad_var,
+                    value: bad_value,
+                    message: bad_message,
+                }) => {
+                    assert_eq!((bad_var.as_str(), bad_value.as_str()), (var, value));
+                    assert!(bad_message.contains(message), "{}: {}", var, bad_message);
+                }
+                other => panic!("expected {} to be rejected, got {:?}", var, other),
+            }
+        }
+    }
+
+    #[test]
+    fn server_settings_are_validated() {
+        type Change = fn(&mut ServerConfig);
+        let cases: Vec<(Change, &str)> = vec![
+            (
+                |config| config.host = " ".to_string(),
+                "host must not be empty",
+            ),
+            (|config| config.port = 0, "port must not be 0"),
+            (
+                |config| config.timeouts.activity_timeout = 0,
+                "timeouts.activity_timeout must be at least 1 second",
+            ),
+            (
+                |config| config.timeouts.pong_timeout = 0,
+                "timeouts.pong_timeout must be at least 1 second",
+            ),
+            (|config| config.apps.clear(), "no apps configured"),
+            (
+                |config| config.app_manager.driver = AppManagerDriver::Json,
+                "app_manager.path is required for the json and sqlite drivers",
+            ),
+            (
+                |config| config.app_manager.watch_interval = 0,
+                "app_manager.watch_interval must be at least 1 second",
+            ),
+            (
+                |config| config.webhook_queue.concurrency = 0,
+                "webhook_queue.concurrency must be at least 1",
+            ),
+            (
+                |config| config.webhook_queue.max_attempts = 0,
+                "webhook_queue.max_attempts must be at least 1",
+            ),
+            (
+                |config| config.webhook_queue.breaker_threshold = 0,
+                "webhook_queue.breaker_threshold must be at least 1",
+            ),
+            (
+                |config| config.webhook_queue.retry_delay = MAX_WEBHOOK_RETRY_DELAY + 1,
+                "webhook_queue.retry_delay must be at most 3600 seconds",
+            ),
+        ];
+        for (change, message) in cases {
+            let mut config = config();
+            change(&mut config);
+            let error = invalid(config);
+            assert!(error.starts_with(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn apps_are_validated() {
+        assert_eq!(
+            invalid_app(|app| app.id = String::new()),
+            "apps[0].id must not be empty"
+        );
+        assert_eq!(
+            invalid_app(|app| app.key = " ".to_string()),
+            "apps[0].key must not be empty"
+        );
+        assert_eq!(
+            invalid_app(|app| app.secret = String::new()),
+            "apps[0].secret must not be empty"
+        );
+        assert_eq!(
+            invalid_app(|app| app.limits.max_buffer_size = 0),
+            "apps[0].limits.max_buffer_size must be at least 1"
+        );
+        assert_eq!(
+            invalid_app(|app| app.timeouts.activity_timeout = Some(0)),
+            "apps[0].timeouts.activity_timeout must be at least 1 second"
+        );
+        assert_eq!(
+            invalid_app(|app| app.timeouts.pong_timeout = Some(0)),
+            "apps[0].timeouts.pong_timeout must be at least 1 second"
+        );
+        assert_eq!(
+            invalid_app(|app| app.webhooks.push(WebhookConfig {
+                url: "ftp://example.com".to_string(),
+                event_types: Vec::new(),
+            })),
+            "apps[0].webhooks[0].url must be an http:// or https:// URL"
+        );
+        assert_eq!(
+            invalid_app(|app| app.authorizer = Some(AuthorizerConfig {
+                url: None,
+                socket: None,
+                path: "/".to_string(),
+                timeout: 5,
+            })),
+            "apps[0].authorizer: exactly one of url and socket must be set"
+        );
+        assert!(
+            invalid_app(|app| app.channel_rules = Some(PathBuf::from("missing-rules.toml")))
+                .starts_with("apps[0].channel_rules: failed to read missing-rules.toml")
+        );
+        assert_eq!(
+            invalid_app(|app| app.jwt = Some(JwtConfig {
+                secret: None,
+                jwks: None,
+                issuer: None,
+                audience: None,
+                required: false,
+            })),
+            "apps[0].jwt: exactly one of secret and jwks must be set"
+        );
+    }
+
+    #[test]
+    fn app_ids_and_keys_are_unique() {
+        assert_eq!(
+            validate_apps(&[app("1", "a"), app("1", "b")]).unwrap_err(),
+            "apps[1].id \"1\" is used by more than one app"
+        );
+        assert_eq!(
+            validate_apps(&[app("1", "a"), app("2", "a")]).unwrap_err(),
+            "apps[1].key \"a\" is used by more than one app"
+        );
+        validate_apps(&[app("1", "a"), app("2", "b")]).unwrap();
+    }
+}
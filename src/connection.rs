@@ -1,48 +1,354 @@
+use crate::auth::rules::RuleDecisions;
+use crate::error::AppError;
 use crate::log::Log;
-use crate::websocket::WebSocket;
+use crate::protocol::close_codes::CloseCode;
+use crate::websocket::WebSocketWriter;
+use serde::Deserialize;
 use serde_json::Value;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
-use tokio::sync::{mpsc, Mutex};
-use tokio::task;
-use web_socket::{CloseReason, Event, Frame};
+use tokio::sync::{Mutex, Notify};
+use tokio::time::Instant;
+use web_socket::{CloseReason, Frame};
+
+/// Number of frames that may wait for the writer task before the backpressure policy applies.
+pub const OUTBOUND_QUEUE_SIZE: usize = 1024;
+
+/// How long an in-flight write may still take once a client has been found over capacity.
+const OVERFLOW_WRITE_GRACE: Duration = Duration::from_secs(5);
+
+/// How long writing and flushing the close frame may take before the socket is given up on.
+const CLOSE_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest close reason that fits a control frame next to the status code.
+const MAX_CLOSE_REASON_LEN: usize = 123;
+
+#[derive(Debug)]
+pub enum OutboundMessage {
+    Text(String),
+    Close { code: u16, reason: String },
+}
+
+/// Outcome of queueing a text frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Push {
+    Queued,
+    /// The queue was full and a frame was discarded by the backpressure policy.
+    Dropped,
+    /// The queue overflowed and the connection is being closed with 4100.
+    OverCapacity,
+    /// The connection is closing or closed and no longer accepts frames.
+    Closed,
+}
+
+/// What to do with a new frame when a client's outbound queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// Discard the oldest queued frame to make room for the new one.
+    DropOldest,
+    /// Discard the new frame and keep what is already queued.
+    DropNewest,
+    /// Close the connection with 4100 (over capacity).
+    #[default]
+    Disconnect,
+}
+
+impl FromStr for BackpressurePolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "drop_oldest" => Ok(Self::DropOldest),
+            "drop_newest" => Ok(Self::DropNewest),
+            "disconnect" => Ok(Self::Disconnect),
+            _ => Err(format!(
+                "unknown backpressure policy {:?}, expected drop_oldest, drop_newest or disconnect",
+                policy
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<String>,
+    close: Option<(u16, String)>,
+    over_capacity: bool,
+    finished: bool,
+}
+
+/// Bounded per-connection queue drained by the writer task.
+///
+/// Frames queued before a close are still flushed; an overflow under
+/// [`BackpressurePolicy::Disconnect`] discards them so the close goes out promptly.
+pub struct OutboundQueue {
+    state: std::sync::Mutex<QueueState>,
+    notify: Notify,
+    overflow: Notify,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    dropped: AtomicU64,
+}
+
+impl OutboundQueue {
+    pub fn new(capacity: usize, policy: BackpressurePolicy) -> Arc<Self> {
+        Arc::new(Self {
+            state: std::sync::Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+            overflow: Notify::new(),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues a text frame, applying the backpressure policy when the queue is full.
+    pub fn push(&self, message: String) -> Push {
+        let mut state = self.state();
+        if state.finished || state.close.is_some() {
+            return Push::Closed;
+        }
+        if state.messages.len() >= self.capacity {
+            match self.policy {
+                BackpressurePolicy::DropOldest => {
+                    state.messages.pop_front();
+                    state.messages.push_back(message);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Push::Dropped;
+                }
+                BackpressurePolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Push::Dropped;
+                }
+                BackpressurePolicy::Disconnect => {
+                    self.dropped
+                        .fetch_add(state.messages.len() as u64 + 1, Ordering::Relaxed);
+                    state.messages.clear();
+                    state.close =
+                        Some((CloseCode::OverCapacity.code(), "Over capacity".to_string()));
+                    state.over_capacity = true;
+                    drop(state);
+                    self.notify.notify_one();
+                    self.overflow.notify_waiters();
+                    return Push::OverCapacity;
+                }
+            }
+        }
+        state.messages.push_back(message);
+        drop(state);
+        self.notify.notify_one();
+        Push::Queued
+    }
+
+    /// Queues a close frame; the first close requested wins.
+    /// Returns `false` if the connection was already closing.
+    pub fn close(&self, code: u16, reason: String) -> bool {
+        let mut state = self.state();
+        if state.finished || state.close.is_some() {
+            return false;
+        }
+        state.close = Some((code, reason));
+        drop(state);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Takes the next frame to write; a pending close frame comes after the queued text.
+    pub fn try_next(&self) -> Option<OutboundMessage> {
+        let mut state = self.state();
+        if let Some(message) = state.messages.pop_front() {
+            return Some(OutboundMessage::Text(message));
+        }
+        let (code, reason) = state.close.take()?;
+        state.finished = true;
+        Some(OutboundMessage::Close { code, reason })
+    }
+
+    /// Waits for the next frame; returns `None` once a close frame has been handed out.
+    pub async fn next(&self) -> Option<OutboundMessage> {
+        loop {
+            if let Some(message) = self.try_next() {
+                return Some(message);
+            }
+            if self.state().finished {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Stops accepting frames, e.g. after the socket failed.
+    pub fn finish(&self) {
+        let mut state = self.state();
+        state.finished = true;
+        state.messages.clear();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.state().messages.len()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Whether a close frame has been queued or the socket has been given up on.
+    pub fn is_closing(&self) -> bool {
+        let state = self.state();
+        state.finished || state.close.is_some()
+    }
+
+    pub fn is_over_capacity(&self) -> bool {
+        self.state().over_capacity
+    }
+
+    /// Resolves once the queue overflowed under [`BackpressurePolicy::Disconnect`].
+    pub async fn over_capacity(&self) {
+        loop {
+            let overflow = self.overflow.notified();
+            if self.is_over_capacity() {
+                return;
+            }
+            overflow.await;
+        }
+    }
+}
+
+/// Newest Pusher protocol version, assumed when a client does not send one.
+pub const DEFAULT_PROTOCOL_VERSION: u8 = 7;
+
+/// What a client reported about itself in the handshake query.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub protocol: u8,
+    /// SDK name, e.g. `js`.
+    pub client: Option<String>,
+    /// SDK version.
+    pub version: Option<String>,
+}
+
+impl Default for ClientInfo {
+    fn default() -> Self {
+        Self {
+            protocol: DEFAULT_PROTOCOL_VERSION,
+            client: None,
+            version: None,
+        }
+    }
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (protocol {})",
+            self.client.as_deref().unwrap_or("unknown client"),
+            self.version.as_deref().unwrap_or("unknown version"),
+            self.protocol
+        )
+    }
+}
 
 pub struct Connection {
     pub socket_id: String,
-    pub socket: Mutex<WebSocket>,
+    pub client_info: ClientInfo,
+    outbound: Arc<OutboundQueue>,
     pub subscribed_channels: Mutex<HashSet<String>>,
     pub user_id: Mutex<Option<String>>,
     pub user_data: Mutex<Option<Value>>,
+    /// Channel rule decisions for the signed-in user.
+    pub rule_decisions: Mutex<RuleDecisions>,
+    pub last_activity: Mutex<Instant>,
 }
 
 impl Connection {
-    pub fn new(socket_id: String, socket: WebSocket) -> Arc<Self> {
-        let connection = Arc::new(Self {
+    pub fn new(
+        socket_id: String,
+        client_info: ClientInfo,
+        outbound: Arc<OutboundQueue>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
             socket_id,
-            socket: Mutex::new(socket),
+            client_info,
+            outbound,
             subscribed_channels: Mutex::new(HashSet::new()),
             user_id: Mutex::new(None),
             user_data: Mutex::new(None),
-        });
-        connection
+            rule_decisions: Mutex::new(RuleDecisions::default()),
+            last_activity: Mutex::new(Instant::now()),
+        })
     }
 
-    pub async fn send_message(&self, message: String) {
-        self.socket
-            .lock()
-            .await
-            .send(message.as_str())
-            .await
-            .expect("TODO: panic message");
+    /// Queues a text frame for the writer task without waiting for the socket.
+    ///
+    /// Frames discarded by the backpressure policy are not an error; a connection
+    /// that is closing or was found over capacity is.
+    pub fn send_message(&self, message: String) -> Result<(), AppError> {
+        match self.outbound.push(message) {
+            Push::Queued => Ok(()),
+            Push::Dropped => {
+                if self.outbound.dropped() == 1 {
+                    Log::warning(format!(
+                        "Outbound queue of {} is full, dropping messages",
+                        self.socket_id
+                    ));
+                }
+                Ok(())
+            }
+            Push::OverCapacity => {
+                Log::warning(format!(
+                    "Outbound queue of {} is full, disconnecting slow client",
+                    self.socket_id
+                ));
+                Err(AppError::ConnectionError(format!(
+                    "Connection {} is over capacity",
+                    self.socket_id
+                )))
+            }
+            Push::Closed => Err(AppError::ConnectionError(format!(
+                "Connection {} is closed",
+                self.socket_id
+            ))),
+        }
+    }
+
+    /// Number of frames waiting to be written to the socket.
+    pub fn buffer_depth(&self) -> usize {
+        self.outbound.depth()
+    }
+
+    /// Number of frames discarded by the backpressure policy so far.
+    pub fn dropped_messages(&self) -> u64 {
+        self.outbound.dropped()
+    }
+
+    /// Whether the connection is being closed and no longer takes frames.
+    pub fn is_closing(&self) -> bool {
+        self.outbound.is_closing()
+    }
+
+    /// Resolves once the client is being disconnected for not draining its queue.
+    pub async fn over_capacity(&self) {
+        self.outbound.over_capacity().await
     }
 
     pub async fn subscribe(&self, channel: String) {
         self.subscribed_channels.lock().await.insert(channel);
     }
 
-    pub async fn unsubscribe(&self, channel: &str) {
-        self.subscribed_channels.lock().await.remove(channel);
+    /// Returns whether the connection was subscribed to `channel`.
+    pub async fn unsubscribe(&self, channel: &str) -> bool {
+        self.subscribed_channels.lock().await.remove(channel)
     }
 
     pub async fn set_user_id(&self, user_id: String) {
@@ -55,45 +361,128 @@ impl Connection {
         *ud = Some(user_data);
     }
 
+    /// Records that the client has just sent something.
+    pub async fn touch(&self) {
+        *self.last_activity.lock().await = Instant::now();
+    }
+
+    pub async fn idle_for(&self) -> Duration {
+        self.last_activity.lock().await.elapsed()
+    }
+
     pub async fn get_subscribed_channels(&self) -> HashSet<String> {
         self.subscribed_channels.lock().await.clone()
     }
 
-    pub async fn close(&self, reason: &str) {
-        self.socket
-            .lock()
-            .await
-            .send_raw(Frame {
-                fin: true,
-                opcode: 8,
-                data: reason.to_bytes().as_ref(),
-            })
-            .await
-            .expect("TODO: panic message");
-        self.socket
-            .lock()
-            .await
-            .stream
-            .flush()
-            .await
-            .expect("TODO: panic message");
+    /// Starts the close handshake; the writer task stops once the close frame has been sent.
+    pub fn close(&self, code: CloseCode, reason: &str) -> Result<(), AppError> {
+        Log::info(format!(
+            "Closing {} with {}: {} (client should {:?})",
+            self.socket_id,
+            code,
+            reason,
+            code.reconnect_strategy()
+        ));
+        self.queue_close(code.code(), reason)
+    }
+
+    /// Answers a close frame from the client by echoing its status code.
+    pub fn acknowledge_close(&self, code: u16) -> Result<(), AppError> {
+        // 1005, 1006 and 1015 must never be put on the wire
+        let code = match code {
+            1000..=1003 | 1007..=1014 | 3000..=4999 => code,
+            _ => CloseCode::Normal.code(),
+        };
+        self.queue_close(code, "")
     }
 
-    pub async fn recv(&self) -> std::io::Result<Event> {
-        self.socket.lock().await.recv().await
+    /// Reasons that would not fit a close frame are cut at the last character that does.
+    fn queue_close(&self, code: u16, reason: &str) -> Result<(), AppError> {
+        let mut end = reason.len().min(MAX_CLOSE_REASON_LEN);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        if self.outbound.close(code, reason[..end].to_string()) {
+            Ok(())
+        } else {
+            Err(AppError::ConnectionError(format!(
+                "Connection {} is already closing",
+                self.socket_id
+            )))
+        }
     }
 }
 
+/// Spawns the task that owns the write half of a socket and drains its outbound queue.
+pub fn spawn_writer(
+    mut writer: WebSocketWriter,
+    capacity: usize,
+    policy: BackpressurePolicy,
+) -> Arc<OutboundQueue> {
+    let queue = OutboundQueue::new(capacity, policy);
+    let outbound = queue.clone();
+    tokio::spawn(async move {
+        while let Some(message) = outbound.next().await {
+            match message {
+                OutboundMessage::Text(text) => {
+                    let write = writer.send(text.as_str());
+                    tokio::pin!(write);
+                    // A client that stopped reading can park this write forever, so once
+                    // the queue has overflowed it only gets a short grace period.
+                    let result = tokio::select! {
+                        result = &mut write => result,
+                        _ = outbound.over_capacity() => {
+                            match tokio::time::timeout(OVERFLOW_WRITE_GRACE, &mut write).await {
+                                Ok(result) => result,
+                                Err(_) => break,
+                            }
+                        }
+                    };
+                    if let Err(e) = result {
+                        Log::error(format!("Failed to write to socket: {}", e));
+                        outbound.finish();
+                        break;
+                    }
+                }
+                OutboundMessage::Close { code, reason } => {
+                    // A client that stopped reading must not keep the writer alive either.
+                    let close = async {
+                        let _ = writer
+                            .send_raw(Frame {
+                                fin: true,
+                                opcode: 8,
+                                data: (code, reason.as_str()).to_bytes().as_ref(),
+                            })
+                            .await;
+                        let _ = writer.stream.flush().await;
+                    };
+                    if tokio::time::timeout(CLOSE_WRITE_TIMEOUT, close)
+                        .await
+                        .is_err()
+                    {
+                        Log::warning(format!("Timed out writing close frame {}", code));
+                    }
+                    break;
+                }
+            }
+        }
+    });
+    queue
+}
+
 pub type SafeConnection = Arc<Connection>;
 
 pub struct ConnectionManager {
     connections: Mutex<HashMap<String, SafeConnection>>,
+    /// Socket ids of the connections signed in as each user.
+    users: Mutex<HashMap<String, HashSet<String>>>,
 }
 
 impl ConnectionManager {
     pub fn new() -> Self {
         Self {
             connections: Mutex::new(HashMap::new()),
+            users: Mutex::new(HashMap::new()),
         }
     }
 
@@ -102,9 +491,63 @@ impl ConnectionManager {
         connections.insert(connection.socket_id.clone(), connection);
     }
 
-    pub async fn remove_connection(&self, socket_id: &str) {
+    /// Adds a connection unless `limit` connections are open already. Counting
+    /// and inserting share one lock, so a burst of handshakes cannot overshoot it.
+    pub async fn try_add_connection(
+        &self,
+        connection: SafeConnection,
+        limit: Option<usize>,
+    ) -> bool {
         let mut connections = self.connections.lock().await;
-        connections.remove(socket_id);
+        if limit.is_some_and(|limit| connections.len() >= limit) {
+            return false;
+        }
+        connections.insert(connection.socket_id.clone(), connection);
+        true
+    }
+
+    /// Removes a connection and drops it from the index of the user it signed in as.
+    ///
+    /// Safe to call again for a connection that is already gone: a sign-in
+    /// handled after the first removal must not leave its user entry behind.
+    pub async fn remove_connection(&self, connection: &Connection) {
+        self.connections.lock().await.remove(&connection.socket_id);
+        let user_id = connection.user_id.lock().await.clone();
+        if let Some(user_id) = user_id {
+            self.remove_user(&connection.socket_id, &user_id).await;
+        }
+    }
+
+    /// Records that `socket_id` is signed in as `user_id`.
+    pub async fn add_user(&self, socket_id: &str, user_id: &str) {
+        let mut users = self.users.lock().await;
+        users
+            .entry(user_id.to_string())
+            .or_default()
+            .insert(socket_id.to_string());
+    }
+
+    pub async fn remove_user(&self, socket_id: &str, user_id: &str) {
+        let mut users = self.users.lock().await;
+        if let Some(sockets) = users.get_mut(user_id) {
+            sockets.remove(socket_id);
+            if sockets.is_empty() {
+                users.remove(user_id);
+            }
+        }
+    }
+
+    /// Returns every connection signed in as `user_id`.
+    pub async fn get_user_connections(&self, user_id: &str) -> Vec<SafeConnection> {
+        let users = self.users.lock().await;
+        let Some(sockets) = users.get(user_id) else {
+            return Vec::new();
+        };
+        let connections = self.connections.lock().await;
+        sockets
+            .iter()
+            .filter_map(|socket_id| connections.get(socket_id).cloned())
+            .collect()
     }
 
     pub async fn get_connection(&self, socket_id: &str) -> Option<SafeConnection> {
@@ -116,6 +559,16 @@ impl ConnectionManager {
         let connections = self.connections.lock().await;
         connections.values().cloned().collect()
     }
+
+    pub async fn connection_count(&self) -> usize {
+        self.connections.lock().await.len()
+    }
+}
+
+impl Default for ConnectionManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub type SafeConnectionManager = Arc<ConnectionManager>;
@@ -123,3 +576,135 @@ pub type SafeConnectionManager = Arc<ConnectionManager>;
 pub fn create_connection_manager() -> SafeConnectionManager {
     Arc::new(ConnectionManager::new())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(message: Option<OutboundMessage>) -> String {
+        match message {
+            Some(OutboundMessage::Text(text)) => text,
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    fn fill(queue: &OutboundQueue) {
+        assert_eq!(queue.push("1".to_string()), Push::Queued);
+        assert_eq!(queue.push("2".to_string()), Push::Queued);
+    }
+
+    #[test]
+    fn drop_oldest_discards_the_front_of_a_full_queue() {
+        let queue = OutboundQueue::new(2, BackpressurePolicy::DropOldest);
+        fill(&queue);
+        assert_eq!(queue.push("3".to_string()), Push::Dropped);
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(text(queue.try_next()), "2");
+        assert_eq!(text(queue.try_next()), "3");
+        assert!(queue.try_next().is_none());
+    }
+
+    #[test]
+    fn drop_newest_discards_the_new_frame() {
+        let queue = OutboundQueue::new(2, BackpressurePolicy::DropNewest);
+        fill(&queue);
+        assert_eq!(queue.push("3".to_string()), Push::Dropped);
+        assert_eq!(queue.push("4".to_string()), Push::Dropped);
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(text(queue.try_next()), "1");
+        assert_eq!(text(queue.try_next()), "2");
+        assert!(queue.try_next().is_none());
+    }
+
+    #[test]
+    fn disconnect_discards_the_queue_and_closes_with_over_capacity() {
+        let queue = OutboundQueue::new(2, BackpressurePolicy::Disconnect);
+        fill(&queue);
+        assert_eq!(queue.push("3".to_string()), Push::OverCapacity);
+        assert!(queue.is_over_capacity());
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(queue.push("4".to_string()), Push::Closed);
+        assert!(!queue.close(CloseCode::Normal.code(), String::new()));
+        match queue.try_next() {
+            Some(OutboundMessage::Close { code, .. }) => {
+                assert_eq!(code, CloseCode::OverCapacity.code())
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn close_frame_follows_queued_text() {
+        let queue = OutboundQueue::new(4, BackpressurePolicy::DropNewest);
+        fill(&queue);
+        assert!(queue.close(4009, "bye".to_string()));
+        assert!(!queue.close(4200, "again".to_string()));
+        assert_eq!(queue.push("3".to_string()), Push::Closed);
+        assert_eq!(text(queue.try_next()), "1");
+        assert_eq!(text(queue.try_next()), "2");
+        match queue.try_next() {
+            Some(OutboundMessage::Close { code, reason }) => {
+                assert_eq!((code, reason.as_str()), (4009, "bye"))
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        assert!(queue.try_next().is_none());
+        assert_eq!(queue.push("4".to_string()), Push::Closed);
+    }
+
+    #[tokio::test]
+    async fn removing_a_connection_again_clears_a_later_sign_in() {
+        let manager = ConnectionManager::new();
+        let outbound = OutboundQueue::new(1, BackpressurePolicy::DropNewest);
+        let connection = Connection::new("1.1".to_string(), ClientInfo::default(), outbound);
+        manager.add_connection(connection.clone()).await;
+        manager.remove_connection(&connection).await;
+
+        connection.set_user_id("7".to_string()).await;
+        manager.add_user("1.1", "7").await;
+        manager.remove_connection(&connection).await;
+        assert_eq!(manager.connection_count().await, 0);
+        assert!(manager.users.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_connections_stop_at_the_limit() {
+        let manager = Arc::new(ConnectionManager::new());
+        let added = futures::future::join_all((0..10).map(|i| {
+            let manager = manager.clone();
+            async move {
+                let outbound = OutboundQueue::new(1, BackpressurePolicy::DropNewest);
+                let connection =
+                    Connection::new(format!("{}.{}", i, i), ClientInfo::default(), outbound);
+                tokio::spawn(async move { manager.try_add_connection(connection, Some(3)).await })
+                    .await
+                    .unwrap()
+            }
+        }))
+        .await;
+        assert_eq!(added.iter().filter(|added| **added).count(), 3);
+        assert_eq!(manager.connection_count().await, 3);
+
+        let outbound = OutboundQueue::new(1, BackpressurePolicy::DropNewest);
+        let connection = Connection::new("11.11".to_string(), ClientInfo::default(), outbound);
+        assert!(manager.try_add_connection(connection, None).await);
+    }
+
+    #[test]
+    fn long_close_reasons_are_cut_at_a_char_boundary() {
+        let outbound = OutboundQueue::new(1, BackpressurePolicy::DropNewest);
+        let connection =
+            Connection::new("1.1".to_string(), ClientInfo::default(), outbound.clone());
+        connection
+            .queue_close(CloseCode::Normal.code(), &"é".repeat(100))
+            .unwrap();
+        match outbound.try_next() {
+            Some(OutboundMessage::Close { reason, .. }) => {
+                assert_eq!(reason, "é".repeat(61))
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+    }
+}
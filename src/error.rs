@@ -1,3 +1,7 @@
+use crate::app_manager::AppManagerError;
+use crate::auth::authorizer::AuthorizerError;
+use crate::channel::ChannelError;
+use crate::webhook::WebhookError;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -69,6 +73,47 @@ impl IntoResponse for AppError {
     }
 }
 
+impl AppError {
+    /// Code sent with a `pusher:error` frame when this error is reported to a client.
+    pub fn pusher_code(&self) -> u32 {
+        match self {
+            AppError::AuthenticationError(_) | AppError::AuthorizationError(_) => 4009,
+            AppError::BadRequest(_)
+            | AppError::ChannelError(_)
+            | AppError::ChannelNotFound(_)
+            | AppError::NotFound(_) => 4000,
+            _ => 4200,
+        }
+    }
+}
+
+impl From<ChannelError> for AppError {
+    fn from(err: ChannelError) -> Self {
+        AppError::ChannelError(err.to_string())
+    }
+}
+
+impl From<AppManagerError> for AppError {
+    fn from(err: AppManagerError) -> Self {
+        AppError::InternalServerError(err.to_string())
+    }
+}
+
+impl From<AuthorizerError> for AppError {
+    fn from(err: AuthorizerError) -> Self {
+        match err {
+            AuthorizerError::Denied(_) => AppError::AuthorizationError(err.to_string()),
+            _ => AppError::InternalServerError(err.to_string()),
+        }
+    }
+}
+
+impl From<WebhookError> for AppError {
+    fn from(err: WebhookError) -> Self {
+        AppError::InternalServerError(err.to_string())
+    }
+}
+
 // Utility function to convert any error to AppError
 pub fn to_app_error<E>(err: E) -> AppError
 where
@@ -1,16 +1,25 @@
-use crate::channel::ChannelType;
+use crate::application::Application;
+use crate::auth::authorizer::authorize;
+use crate::auth::rules::presence_channel_data;
+use crate::auth::{generate_auth_signature, sign};
+use crate::channel::{
+    is_cache_channel, server_to_user_id, validate_channel_name, ChannelType, SERVER_TO_USER_PREFIX,
+};
 use crate::error::AppError;
+use crate::handlers::websocket::terminate_connection;
 use crate::log::Log;
-use crate::protocol::events::{PusherApiEvent};
+use crate::protocol::close_codes::CloseCode;
+use crate::protocol::events::{PusherApiEvent, PusherBatchEvents};
 use crate::server::AppState;
-use axum::extract::Query;
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Map, Value};
+use std::time::Instant;
 
 #[derive(Deserialize)]
 pub struct AuthRequest {
@@ -23,42 +32,111 @@ pub struct AuthRequest {
 #[derive(Serialize)]
 pub struct AuthResponse {
     auth: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel_data: Option<String>,
 }
 
+/// Signs a private or presence subscription, accepting the JSON or form bodies
+/// sent by Pusher clients.
+///
+/// Channels covered by the app's channel rules are decided by them for the
+/// user the socket signed in as, who also becomes the presence member. Apps
+/// with an authorizer only get a signature for other channels once their
+/// backend approves, and presence subscriptions then use the `channel_data` it
+/// returned. Anything else is refused unless the app has `open_auth` set.
 pub async fn auth(
     State(state): State<AppState>,
     Path(app_id): Path<String>,
-    Json(payload): Json<AuthRequest>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Result<impl IntoResponse, AppError> {
     let app = state
         .application_manager
         .get_application(&app_id)
-        .await
+        .await?
         .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
 
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let payload: AuthRequest = if is_json {
+        serde_json::from_slice(&body).map_err(|e| e.to_string())
+    } else {
+        serde_urlencoded::from_bytes(&body).map_err(|e| e.to_string())
+    }
+    .map_err(|e| AppError::BadRequest(format!("Invalid auth request: {}", e)))?;
+
     let channel_type = determine_channel_type(&payload.channel_name);
+    if channel_type == ChannelType::Public {
+        return Err(AppError::BadRequest(
+            "Public channels don't need authentication".into(),
+        ));
+    }
 
-    match channel_type {
-        ChannelType::Private | ChannelType::Presence => {
-            // In a real implementation, you'd verify the user's credentials here
-            let auth_signature = generate_auth_signature(
-                &app.key,
-                &app.secret,
-                &payload.socket_id,
-                &payload.channel_name,
-                payload.channel_data.as_deref(),
-            );
-            Ok((
-                StatusCode::OK,
-                Json(AuthResponse {
-                    auth: auth_signature,
-                }),
+    let connection = app
+        .connection_manager
+        .get_connection(&payload.socket_id)
+        .await;
+    let ruling = match (&app.channel_rules, &connection) {
+        (Some(rules), Some(connection)) => {
+            rules.evaluate_for(&payload.channel_name, connection).await
+        }
+        (Some(rules), None) => rules.evaluate(&payload.channel_name, None),
+        (None, _) => None,
+    };
+
+    let channel_data = match (ruling, &app.authorizer) {
+        (Some(false), _) => {
+            return Err(AppError::AuthorizationError(
+                "Not allowed by the channel rules".into(),
             ))
         }
-        ChannelType::Public => Err(AppError::BadRequest(
-            "Public channels don't need authentication".into(),
-        )),
-    }
+        (Some(true), _) => match &connection {
+            Some(connection) => presence_channel_data(connection).await,
+            None => None,
+        },
+        (None, Some(authorizer)) => authorize(
+            authorizer,
+            &headers,
+            &payload.socket_id,
+            &payload.channel_name,
+        )
+        .await
+        .inspect_err(|e| {
+            Log::warning(format!(
+                "Refused auth of {} for {}: {}",
+                payload.socket_id, payload.channel_name, e
+            ))
+        })?,
+        (None, None) if app.open_auth => payload.channel_data,
+        (None, None) => {
+            return Err(AppError::AuthorizationError(
+                "No authorizer or channel rule allows this channel".into(),
+            ))
+        }
+    };
+    let channel_data = match channel_type {
+        ChannelType::Presence => Some(channel_data.ok_or_else(|| {
+            AppError::AuthorizationError("Presence channels require channel_data".into())
+        })?),
+        _ => None,
+    };
+
+    let auth_signature = generate_auth_signature(
+        &app.key,
+        &app.secret,
+        &payload.socket_id,
+        &payload.channel_name,
+        channel_data.as_deref(),
+    );
+    Ok((
+        StatusCode::OK,
+        Json(AuthResponse {
+            auth: auth_signature,
+            channel_data,
+        }),
+    ))
 }
 
 pub async fn channel_users(
@@ -68,69 +146,384 @@ pub async fn channel_users(
     let app = state
         .application_manager
         .get_application(&app_id)
-        .await
+        .await?
         .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
 
-    let channel = app
-        .channel_manager
-        .get_channel(&channel_name)
-        .await
-        .unwrap()
-        .unwrap();
+    if determine_channel_type(&channel_name) != ChannelType::Presence {
+        return Err(AppError::BadRequest(
+            "Users can only be retrieved for presence channels".into(),
+        ));
+    }
+
+    let channel = app.channel_manager.get_channel(&channel_name).await?;
+    let users = match channel.as_ref().and_then(|channel| channel.as_presence()) {
+        Some(presence) => presence.get_presence_users().await?,
+        None => Vec::new(),
+    };
+    let users: Vec<Value> = users
+        .into_iter()
+        .map(|user| json!({ "id": user.user_id }))
+        .collect();
 
-    Ok((StatusCode::OK, Json(channel.subscribers().await)))
+    Ok((StatusCode::OK, Json(json!({ "users": users }))))
+}
+
+#[derive(Deserialize)]
+pub struct ChannelStateQuery {
+    info: Option<String>,
 }
 
 pub async fn channel_state(
     State(state): State<AppState>,
     Path((app_id, channel_name)): Path<(String, String)>,
+    Query(query): Query<ChannelStateQuery>,
 ) -> Result<impl IntoResponse, AppError> {
     let app = state
         .application_manager
         .get_application(&app_id)
-        .await
+        .await?
         .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
 
-    let channel = app
-        .channel_manager
-        .get_channel(&channel_name)
-        .await
-        .unwrap()
-        .ok_or_else(|| AppError::NotFound("Channel not found".into()))?;
+    let info = requested_info(query.info.as_deref());
+    if info.contains(&"user_count")
+        && determine_channel_type(&channel_name) != ChannelType::Presence
+    {
+        return Err(AppError::BadRequest(
+            "user_count may only be requested for presence channels".into(),
+        ));
+    }
 
-    let subscriber_count = channel.subscriber_count().await.unwrap();
+    // Unknown channels are simply unoccupied
+    let subscriber_count = match app.channel_manager.get_channel(&channel_name).await? {
+        Some(channel) => channel.subscriber_count().await?,
+        None => 0,
+    };
 
-    let state = serde_json::json!({
-        "occupied": subscriber_count > 0,
-        "subscription_count": subscriber_count,
-    });
+    let mut state = Map::new();
+    state.insert("occupied".into(), (subscriber_count > 0).into());
+    if let Value::Object(attributes) = channel_info(&app, &channel_name, &info).await? {
+        state.extend(attributes);
+    }
 
-    Ok((StatusCode::OK, Json(state)))
+    Ok((StatusCode::OK, Json(Value::Object(state))))
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct EventQuery {
-    auth_key: String,
-    auth_timestamp: String,
-    auth_version: String,
-    body_md5: String,
-    auth_signature: String,
+#[derive(Deserialize)]
+pub struct ChannelsQuery {
+    filter_by_prefix: Option<String>,
+    info: Option<String>,
 }
+
+pub async fn channels(
+    State(state): State<AppState>,
+    Path(app_id): Path<String>,
+    Query(query): Query<ChannelsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let app = state
+        .application_manager
+        .get_application(&app_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
+
+    let prefix = query.filter_by_prefix.unwrap_or_default();
+    let info = requested_info(query.info.as_deref());
+    if info.contains(&"user_count") && !prefix.starts_with("presence-") {
+        return Err(AppError::BadRequest(
+            "user_count may only be requested when filtering by the presence- prefix".into(),
+        ));
+    }
+
+    let mut channels_info = Map::new();
+    for channel in app.channel_manager.get_channels().await? {
+        if !channel.name().starts_with(&prefix) || channel.subscriber_count().await? == 0 {
+            continue;
+        }
+        let attributes = channel_info(&app, channel.name(), &info).await?;
+        channels_info.insert(channel.name().to_string(), attributes);
+    }
+
+    Ok((StatusCode::OK, Json(json!({ "channels": channels_info }))))
+}
+
+const MAX_BATCH_SIZE: usize = 10;
+const MAX_EVENT_NAME_LENGTH: usize = 200;
+const MAX_CHANNELS_PER_EVENT: usize = 100;
+const MAX_EVENT_DATA_SIZE: usize = 10 * 1024;
+
 pub async fn events(
     State(state): State<AppState>,
     Path(app_id): Path<String>,
-    Query(query): Query<EventQuery>,
     Json(event): Json<PusherApiEvent>,
 ) -> Result<impl IntoResponse, AppError> {
     let app = state
         .application_manager
         .get_application(&app_id)
-        .await
+        .await?
         .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
     let message = serde_json::to_string(&event)?;
     Log::info(format!("Received event: {}", message));
-    let channels = event.channels;
 
+    let channels = validate_event(&event)?;
+    publish_event(&app, &event, &channels).await?;
+
+    let info = requested_info(event.info.as_deref());
+    if info.is_empty() {
+        return Ok((StatusCode::OK, Json(json!({}))));
+    }
+    let mut channels_info = Map::new();
+    for channel_name in channels {
+        let attributes = channel_info(&app, &channel_name, &info).await?;
+        channels_info.insert(channel_name, attributes);
+    }
+
+    Ok((StatusCode::OK, Json(json!({ "channels": channels_info }))))
+}
+
+pub async fn batch_events(
+    State(state): State<AppState>,
+    Path(app_id): Path<String>,
+    Json(payload): Json<PusherBatchEvents>,
+) -> Result<impl IntoResponse, AppError> {
+    let app = state
+        .application_manager
+        .get_application(&app_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
+
+    if payload.batch.len() > MAX_BATCH_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "Batch must not contain more than {} events",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    // Validate the whole batch first so it is either published entirely or not at all
+    let mut batch = Vec::with_capacity(payload.batch.len());
+    for event in payload.batch {
+        let channels = validate_event(&event)?;
+        if channels.len() != 1 {
+            return Err(AppError::BadRequest(
+                "Batch events must target exactly one channel".into(),
+            ));
+        }
+        batch.push((event, channels));
+    }
+
+    let mut batch_info = Vec::with_capacity(batch.len());
+    for (event, channels) in &batch {
+        publish_event(&app, event, channels).await?;
+        let info = requested_info(event.info.as_deref());
+        batch_info.push(channel_info(&app, &channels[0], &info).await?);
+    }
+
+    if batch.iter().all(|(event, _)| event.info.is_none()) {
+        return Ok((StatusCode::OK, Json(json!({}))));
+    }
+    Ok((StatusCode::OK, Json(json!({ "batch": batch_info }))))
+}
+
+/// Sends an event to every socket signed in as `user_id`, on its
+/// `#server-to-user-{id}` channel. The body is that of `/events` without channels.
+pub async fn user_events(
+    State(state): State<AppState>,
+    Path((app_id, user_id)): Path<(String, String)>,
+    Json(mut event): Json<PusherApiEvent>,
+) -> Result<impl IntoResponse, AppError> {
+    let app = state
+        .application_manager
+        .get_application(&app_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
+    if event.channel.is_some() || !event.channels.is_empty() {
+        return Err(AppError::BadRequest(
+            "User events are sent to the user's channel; omit channel and channels".into(),
+        ));
+    }
+    event.channel = Some(format!("{}{}", SERVER_TO_USER_PREFIX, user_id));
+
+    let channels = validate_event(&event)?;
+    publish_event(&app, &event, &channels).await?;
+
+    Ok((StatusCode::OK, Json(json!({}))))
+}
+
+/// Disconnects every socket signed in as `user_id` with a 4009 close and removes
+/// them from their channels.
+pub async fn terminate_user_connections(
+    State(state): State<AppState>,
+    Path((app_id, user_id)): Path<(String, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let app = state
+        .application_manager
+        .get_application(&app_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
+
+    let connections = app.connection_manager.get_user_connections(&user_id).await;
+    Log::info(format!(
+        "Terminating {} connections of user {}",
+        connections.len(),
+        user_id
+    ));
+    for connection in connections {
+        terminate_connection(
+            &connection,
+            &app,
+            CloseCode::Unauthorized,
+            "Connection terminated by the app",
+        )
+        .await;
+    }
+
+    Ok((StatusCode::OK, Json(json!({}))))
+}
+
+/// Lists the app's open connections with how far behind each client is:
+/// the frames waiting in its outbound queue and those discarded so far.
+pub async fn connections(
+    State(state): State<AppState>,
+    Path(app_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let app = state
+        .application_manager
+        .get_application(&app_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
+
+    let mut connections = Vec::new();
+    for connection in app.connection_manager.get_connections().await {
+        let user_id = connection.user_id.lock().await.clone();
+        connections.push(json!({
+            "socket_id": connection.socket_id,
+            "user_id": user_id,
+            "buffer_depth": connection.buffer_depth(),
+            "dropped_messages": connection.dropped_messages(),
+        }));
+    }
+
+    Ok((StatusCode::OK, Json(json!({ "connections": connections }))))
+}
+
+const DEFAULT_DEAD_LETTER_LIMIT: usize = 100;
+const MAX_DEAD_LETTER_LIMIT: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct DeadLettersQuery {
+    limit: Option<usize>,
+}
+
+/// Lists webhook deliveries of the app that used up their attempts, oldest first.
+pub async fn dead_letters(
+    State(state): State<AppState>,
+    Path(app_id): Path<String>,
+    Query(query): Query<DeadLettersQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DEAD_LETTER_LIMIT)
+        .min(MAX_DEAD_LETTER_LIMIT);
+    let dead_letters: Vec<Value> = state
+        .webhook_queue
+        .dead_letters(&app_id, limit)
+        .await?
+        .into_iter()
+        .map(|dead_letter| {
+            let body =
+                serde_json::from_str(&dead_letter.body).unwrap_or(Value::String(dead_letter.body));
+            json!({
+                "id": dead_letter.id,
+                "url": dead_letter.url,
+                "attempts": dead_letter.attempts,
+                "last_error": dead_letter.last_error,
+                "failed_at": dead_letter.failed_at,
+                "body": body,
+            })
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "dead_letters": dead_letters })),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ReplayRequest {
+    ids: Option<Vec<i64>>,
+}
+
+/// Queues dead-lettered deliveries again, the listed `ids` or all of them when
+/// the body is empty, signed with the app's current secret.
+pub async fn replay_dead_letters(
+    State(state): State<AppState>,
+    Path(app_id): Path<String>,
+    body: Bytes,
+) -> Result<impl IntoResponse, AppError> {
+    let app = state
+        .application_manager
+        .get_application(&app_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Application not found".into()))?;
+    let ids = if body.is_empty() {
+        None
+    } else {
+        serde_json::from_slice::<ReplayRequest>(&body)
+            .map_err(|e| AppError::BadRequest(format!("Invalid replay request: {}", e)))?
+            .ids
+    };
+
+    let secret = app.secret.clone();
+    let replayed = state
+        .webhook_queue
+        .replay(&app_id, ids, app.key.clone(), move |body| {
+            sign(&secret, body)
+        })
+        .await?;
+    Log::webhook_sender(format!(
+        "Replaying {} dead-lettered webhooks of app {}",
+        replayed, app_id
+    ));
+
+    Ok((StatusCode::OK, Json(json!({ "replayed": replayed }))))
+}
+
+/// Checks an API event against the Pusher limits and returns its target channels.
+fn validate_event(event: &PusherApiEvent) -> Result<Vec<String>, AppError> {
+    if event.name.is_empty() || event.name.len() > MAX_EVENT_NAME_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Event name must be between 1 and {} characters",
+            MAX_EVENT_NAME_LENGTH
+        )));
+    }
+    if event.data.len() > MAX_EVENT_DATA_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "Event data must not exceed {} bytes",
+            MAX_EVENT_DATA_SIZE
+        )));
+    }
+
+    let mut channels = event.channels.clone();
+    if let Some(channel) = &event.channel {
+        channels.push(channel.clone());
+    }
+    if channels.is_empty() || channels.len() > MAX_CHANNELS_PER_EVENT {
+        return Err(AppError::BadRequest(format!(
+            "Events must target between 1 and {} channels",
+            MAX_CHANNELS_PER_EVENT
+        )));
+    }
+    for channel in &channels {
+        validate_channel_name(channel)
+            .map_err(|e| AppError::BadRequest(format!("{}: {}", e, channel)))?;
+    }
+    Ok(channels)
+}
+
+async fn publish_event(
+    app: &Application,
+    event: &PusherApiEvent,
+    channels: &[String],
+) -> Result<(), AppError> {
     Log::info(format!("Broadcasting event to channels: {:?}", channels));
     for channel_name in channels {
         let message = json!({
@@ -138,20 +531,95 @@ pub async fn events(
             "data": event.data,
             "channel": channel_name,
         });
+        if let Some(user_id) = server_to_user_id(channel_name) {
+            send_to_user(app, user_id, &message, event.socket_id.as_deref()).await;
+            continue;
+        }
+        if is_cache_channel(channel_name) {
+            app.channel_manager
+                .cache_event(channel_name, event.data.clone())
+                .await?;
+        }
+        // Nobody is subscribed to a channel that does not exist, so there is nothing to do
+        let Some(channel) = app.channel_manager.get_channel(channel_name).await? else {
+            continue;
+        };
         Log::info(format!("Broadcasting event to channel: {}", channel_name));
-        let channel = app
-            .channel_manager
-            .get_channel(&channel_name)
-            .await
-            .unwrap()
-            .ok_or_else(|| AppError::NotFound("Channel not found".into()))?;
+        channel
+            .broadcast(message.to_string(), event.socket_id.as_deref())
+            .await?;
+    }
+    Ok(())
+}
 
-        channel.broadcast(message.to_string()).await.unwrap();
+/// Delivers `message` to the sockets signed in as `user_id`, except the socket `except`.
+async fn send_to_user(app: &Application, user_id: &str, message: &Value, except: Option<&str>) {
+    let connections = app.connection_manager.get_user_connections(user_id).await;
+    Log::info(format!(
+        "Sending event to {} sockets of user {}",
+        connections.len(),
+        user_id
+    ));
+    for connection in connections {
+        if Some(connection.socket_id.as_str()) == except {
+            continue;
+        }
+        let _ = connection.send_message(message.to_string());
     }
+}
 
-    Log::info(format!("Event data: {:?}", event.data));
+/// Splits the comma separated `info` attribute list of a request.
+fn requested_info(info: Option<&str>) -> Vec<&str> {
+    info.map(|info| {
+        info.split(',')
+            .map(str::trim)
+            .filter(|attribute| !attribute.is_empty())
+            .collect()
+    })
+    .unwrap_or_default()
+}
 
-    Ok(StatusCode::OK)
+/// Builds the requested `info` attributes of a channel.
+async fn channel_info(
+    app: &Application,
+    channel_name: &str,
+    info: &[&str],
+) -> Result<Value, AppError> {
+    let mut attributes = Map::new();
+    let channel = app.channel_manager.get_channel(channel_name).await?;
+    for attribute in info {
+        match *attribute {
+            "subscription_count" => {
+                let count = match &channel {
+                    Some(channel) => channel.subscriber_count().await?,
+                    None => 0,
+                };
+                attributes.insert("subscription_count".into(), count.into());
+            }
+            "user_count" if determine_channel_type(channel_name) == ChannelType::Presence => {
+                let count = match channel.as_ref().and_then(|channel| channel.as_presence()) {
+                    Some(presence) => presence.get_presence_users().await?.len(),
+                    None => 0,
+                };
+                attributes.insert("user_count".into(), count.into());
+            }
+            "cache" if is_cache_channel(channel_name) => {
+                let cache = app
+                    .channel_manager
+                    .get_cached_event(channel_name)
+                    .await?
+                    .map(|cached| {
+                        let ttl = cached.expires_at.saturating_duration_since(Instant::now());
+                        json!({ "data": cached.data, "ttl": ttl.as_secs() })
+                    });
+                if let Some(cache) = cache {
+                    attributes.insert("cache".into(), cache);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Value::Object(attributes))
 }
 
 fn determine_channel_type(channel_name: &str) -> ChannelType {
@@ -164,25 +632,552 @@ fn determine_channel_type(channel_name: &str) -> ChannelType {
     }
 }
 
-fn generate_auth_signature(
-    app_key: &str,
-    app_secret: &str,
-    socket_id: &str,
-    channel_name: &str,
-    channel_data: Option<&str>,
-) -> String {
-    use hex;
-    use sha2::{Digest, Sha256};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_manager::static_app_manager::StaticAppManager;
+    use crate::application::ApplicationManager;
+    use crate::channel::{PresenceUser, CACHED_EVENT_TTL};
+    use crate::config::{AppConfig, AppLimits, AppTimeouts, TimeoutConfig, WebhookQueueConfig};
+    use crate::connection::{
+        BackpressurePolicy, ClientInfo, Connection, OutboundMessage, OutboundQueue, SafeConnection,
+    };
+    use crate::webhook::queue::WebhookQueue;
+    use std::path::PathBuf;
+    use std::sync::Arc;
 
-    let mut string_to_sign = format!("{}:{}:{}", socket_id, channel_name, app_secret);
-    if let Some(data) = channel_data {
-        string_to_sign.push(':');
-        string_to_sign.push_str(data);
+    /// State serving the single app `1`, with an in-memory webhook queue.
+    async fn state() -> AppState {
+        let app = AppConfig {
+            id: "1".to_string(),
+            key: "key".to_string(),
+            secret: "secret".to_string(),
+            enabled: true,
+            enable_client_messages: true,
+            limits: AppLimits::default(),
+            timeouts: AppTimeouts::default(),
+            webhooks: Vec::new(),
+            authorizer: None,
+            open_auth: false,
+            channel_rules: None,
+            jwt: None,
+        };
+        let webhook_queue = WebhookQueue::start(&WebhookQueueConfig {
+            path: PathBuf::from(":memory:"),
+            ..WebhookQueueConfig::default()
+        })
+        .await
+        .unwrap();
+        let application_manager = ApplicationManager::new(
+            Arc::new(StaticAppManager::new(vec![app])),
+            TimeoutConfig::default(),
+            webhook_queue.clone(),
+        );
+        AppState {
+            application_manager: Arc::new(application_manager),
+            webhook_queue,
+        }
     }
 
-    let mut hasher = Sha256::new();
-    hasher.update(string_to_sign);
-    let result = hasher.finalize();
+    async fn live_app(state: &AppState) -> Arc<Application> {
+        state
+            .application_manager
+            .get_application("1")
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    fn connection(socket_id: &str) -> (SafeConnection, Arc<OutboundQueue>) {
+        let outbound = OutboundQueue::new(16, BackpressurePolicy::DropNewest);
+        let connection = Connection::new(
+            socket_id.to_string(),
+            ClientInfo::default(),
+            outbound.clone(),
+        );
+        (connection, outbound)
+    }
+
+    /// Subscribes a new connection to the public or private `channel` and returns its queue.
+    async fn subscriber(app: &Application, socket_id: &str, channel: &str) -> Arc<OutboundQueue> {
+        let (connection, outbound) = connection(socket_id);
+        let channel_type = determine_channel_type(channel);
+        app.channel_manager
+            .subscribe(channel.to_string(), channel_type, &connection, None)
+            .await
+            .unwrap();
+        outbound
+    }
+
+    /// Joins the presence `channel` with a new connection as `user_id`.
+    async fn member(app: &Application, socket_id: &str, channel: &str, user_id: &str) {
+        let (connection, _) = connection(socket_id);
+        let user = PresenceUser {
+            user_id: user_id.to_string(),
+            user_info: json!({}),
+        };
+        app.channel_manager
+            .subscribe(
+                channel.to_string(),
+                ChannelType::Presence,
+                &connection,
+                Some(user),
+            )
+            .await
+            .unwrap();
+    }
+
+    /// Connects a socket to the app and signs it in as `user_id`.
+    async fn signed_in(
+        app: &Application,
+        socket_id: &str,
+        user_id: &str,
+    ) -> (SafeConnection, Arc<OutboundQueue>) {
+        let (connection, outbound) = connection(socket_id);
+        app.connection_manager
+            .add_connection(connection.clone())
+            .await;
+        connection.set_user_id(user_id.to_string()).await;
+        app.connection_manager.add_user(socket_id, user_id).await;
+        (connection, outbound)
+    }
+
+    /// Subscribes `connection` the way `pusher:subscribe` does, as its user on presence channels.
+    async fn join(app: &Application, connection: &SafeConnection, channel: &str) {
+        let channel_type = determine_channel_type(channel);
+        let member = match channel_type {
+            ChannelType::Presence => Some(PresenceUser {
+                user_id: connection.user_id.lock().await.clone().unwrap(),
+                user_info: json!({}),
+            }),
+            _ => None,
+        };
+        app.subscribe(channel, channel_type, connection, member)
+            .await
+            .unwrap();
+        connection.subscribe(channel.to_string()).await;
+    }
+
+    fn batch(events: Value) -> Json<PusherBatchEvents> {
+        Json(serde_json::from_value(json!({ "batch": events })).unwrap())
+    }
+
+    fn event(channel: &str) -> Value {
+        json!({ "name": "update", "channel": channel, "data": "{}" })
+    }
+
+    async fn body(response: impl IntoResponse) -> Value {
+        let bytes = axum::body::to_bytes(response.into_response().into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn batches_are_limited_to_ten_events() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let outbound = subscriber(&app, "1.1", "orders").await;
+
+        let events: Vec<Value> = (0..=MAX_BATCH_SIZE).map(|_| event("orders")).collect();
+        let result =
+            batch_events(State(state.clone()), Path("1".into()), batch(json!(events))).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        assert!(outbound.try_next().is_none());
+
+        let events: Vec<Value> = (0..MAX_BATCH_SIZE).map(|_| event("orders")).collect();
+        let result = batch_events(State(state), Path("1".into()), batch(json!(events))).await;
+        assert_eq!(body(result.ok().unwrap()).await, json!({}));
+        for _ in 0..MAX_BATCH_SIZE {
+            assert!(outbound.try_next().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_events_target_exactly_one_channel() {
+        let state = state().await;
+        for event in [
+            json!({ "name": "update", "channels": ["a", "b"], "data": "{}" }),
+            json!({ "name": "update", "channel": "a", "channels": ["b"], "data": "{}" }),
+            json!({ "name": "update", "data": "{}" }),
+        ] {
+            let result = batch_events(
+                State(state.clone()),
+                Path("1".into()),
+                batch(json!([event])),
+            )
+            .await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn nothing_is_published_when_any_batch_event_is_invalid() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let outbound = subscriber(&app, "1.1", "orders").await;
+
+        let too_large = "x".repeat(MAX_EVENT_DATA_SIZE + 1);
+        for invalid in [
+            json!({ "name": "", "channel": "orders", "data": "{}" }),
+            json!({ "name": "update", "channel": "orders", "data": too_large }),
+            json!({ "name": "update", "channel": "bad channel", "data": "{}" }),
+        ] {
+            let events = json!([event("orders"), invalid, event("orders")]);
+            let result = batch_events(State(state.clone()), Path("1".into()), batch(events)).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+            assert!(outbound.try_next().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_info_is_reported_per_event() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let _first = subscriber(&app, "1.1", "orders").await;
+        let _second = subscriber(&app, "1.2", "orders").await;
+
+        let events = json!([
+            { "name": "update", "channel": "orders", "data": "{}", "info": "subscription_count" },
+            event("orders"),
+            { "name": "update", "channel": "empty", "data": "{}", "info": "subscription_count" },
+        ]);
+        let result = batch_events(State(state), Path("1".into()), batch(events)).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "batch": [
+                { "subscription_count": 2 },
+                {},
+                { "subscription_count": 0 },
+            ] })
+        );
+    }
+
+    fn channels_query(filter_by_prefix: Option<&str>, info: Option<&str>) -> Query<ChannelsQuery> {
+        Query(ChannelsQuery {
+            filter_by_prefix: filter_by_prefix.map(str::to_string),
+            info: info.map(str::to_string),
+        })
+    }
+
+    #[tokio::test]
+    async fn channels_lists_occupied_channels_matching_the_prefix() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let _orders = subscriber(&app, "1.1", "orders").await;
+        let _chat = subscriber(&app, "1.2", "private-chat").await;
+        member(&app, "1.3", "presence-team", "alice").await;
+        member(&app, "1.4", "presence-team", "alice").await;
+        member(&app, "1.5", "presence-team", "bob").await;
+        member(&app, "1.6", "presence-lobby", "carol").await;
+
+        let result = channels(
+            State(state.clone()),
+            Path("1".into()),
+            channels_query(None, None),
+        )
+        .await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "channels": {
+                "orders": {},
+                "private-chat": {},
+                "presence-team": {},
+                "presence-lobby": {},
+            } })
+        );
+
+        let query = channels_query(Some("private-"), None);
+        let result = channels(State(state.clone()), Path("1".into()), query).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "channels": { "private-chat": {} } })
+        );
 
-    format!("{}:{}", app_key, hex::encode(result))
+        let query = channels_query(Some("presence-"), Some("user_count"));
+        let result = channels(State(state), Path("1".into()), query).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "channels": {
+                "presence-team": { "user_count": 2 },
+                "presence-lobby": { "user_count": 1 },
+            } })
+        );
+    }
+
+    #[tokio::test]
+    async fn channels_only_count_users_of_presence_channels() {
+        let state = state().await;
+        for prefix in [None, Some("private-"), Some("presence")] {
+            let query = channels_query(prefix, Some("user_count"));
+            let result = channels(State(state.clone()), Path("1".into()), query).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    fn state_query(info: Option<&str>) -> Query<ChannelStateQuery> {
+        Query(ChannelStateQuery {
+            info: info.map(str::to_string),
+        })
+    }
+
+    fn channel_path(channel: &str) -> Path<(String, String)> {
+        Path(("1".to_string(), channel.to_string()))
+    }
+
+    #[tokio::test]
+    async fn unknown_channels_are_unoccupied() {
+        let state = state().await;
+        let query = state_query(Some("subscription_count"));
+        let result = channel_state(State(state.clone()), channel_path("orders"), query).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "occupied": false, "subscription_count": 0 })
+        );
+
+        let query = state_query(Some("user_count"));
+        let result = channel_state(State(state), channel_path("presence-team"), query).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "occupied": false, "user_count": 0 })
+        );
+    }
+
+    #[tokio::test]
+    async fn channel_state_counts_subscriptions_and_users() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        member(&app, "1.1", "presence-team", "alice").await;
+        member(&app, "1.2", "presence-team", "alice").await;
+        member(&app, "1.3", "presence-team", "bob").await;
+
+        let query = state_query(Some("subscription_count,user_count"));
+        let result = channel_state(State(state), channel_path("presence-team"), query).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "occupied": true, "subscription_count": 3, "user_count": 2 })
+        );
+    }
+
+    #[tokio::test]
+    async fn user_count_is_only_reported_for_presence_channels() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let _orders = subscriber(&app, "1.1", "private-orders").await;
+
+        for channel in ["orders", "private-orders"] {
+            let query = state_query(Some("user_count"));
+            let result = channel_state(State(state.clone()), channel_path(channel), query).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn channel_users_lists_each_presence_member_once() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        member(&app, "1.1", "presence-team", "alice").await;
+        member(&app, "1.2", "presence-team", "alice").await;
+        member(&app, "1.3", "presence-team", "bob").await;
+
+        let result = channel_users(State(state.clone()), channel_path("presence-team")).await;
+        let mut users = body(result.ok().unwrap()).await["users"]
+            .as_array()
+            .unwrap()
+            .clone();
+        users.sort_by_key(|user| user["id"].as_str().unwrap().to_string());
+        assert_eq!(
+            users,
+            vec![json!({ "id": "alice" }), json!({ "id": "bob" })]
+        );
+
+        let result = channel_users(State(state), channel_path("presence-empty")).await;
+        assert_eq!(body(result.ok().unwrap()).await, json!({ "users": [] }));
+    }
+
+    #[tokio::test]
+    async fn channel_users_requires_a_presence_channel() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let _orders = subscriber(&app, "1.1", "private-orders").await;
+
+        for channel in ["orders", "private-orders"] {
+            let result = channel_users(State(state.clone()), channel_path(channel)).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_channels_report_their_last_event() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let _news = subscriber(&app, "1.1", "cache-news").await;
+        for data in ["first", "second"] {
+            let event = json!({ "name": "update", "channel": "cache-news", "data": data });
+            let event = Json(serde_json::from_value(event).unwrap());
+            events(State(state.clone()), Path("1".into()), event)
+                .await
+                .ok()
+                .unwrap();
+        }
+
+        let query = state_query(Some("cache"));
+        let result = channel_state(State(state.clone()), channel_path("cache-news"), query).await;
+        let channel = body(result.ok().unwrap()).await;
+        assert_eq!(channel["occupied"], true);
+        assert_eq!(channel["cache"]["data"], "second");
+        let ttl = channel["cache"]["ttl"].as_u64().unwrap();
+        assert!(ttl > 0 && ttl <= CACHED_EVENT_TTL.as_secs());
+
+        // Other channels have no cache attribute even when it is requested
+        let _orders = subscriber(&app, "1.2", "orders").await;
+        let query = state_query(Some("cache"));
+        let result = channel_state(State(state), channel_path("orders"), query).await;
+        assert_eq!(
+            body(result.ok().unwrap()).await,
+            json!({ "occupied": true })
+        );
+    }
+
+    fn user_event(event: Value) -> Json<PusherApiEvent> {
+        Json(serde_json::from_value(event).unwrap())
+    }
+
+    fn user_path(user_id: &str) -> Path<(String, String)> {
+        Path(("1".to_string(), user_id.to_string()))
+    }
+
+    #[tokio::test]
+    async fn user_events_reach_every_socket_of_the_user_only() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let (_, first) = signed_in(&app, "1.1", "alice").await;
+        let (_, second) = signed_in(&app, "1.2", "alice").await;
+        let (_, other) = signed_in(&app, "1.3", "bob").await;
+
+        let event = user_event(json!({ "name": "notice", "data": "hi" }));
+        let result = user_events(State(state.clone()), user_path("alice"), event).await;
+        assert_eq!(body(result.ok().unwrap()).await, json!({}));
+        for outbound in [&first, &second] {
+            match outbound.try_next() {
+                Some(OutboundMessage::Text(text)) => assert_eq!(
+                    serde_json::from_str::<Value>(&text).unwrap(),
+                    json!({
+                        "event": "notice",
+                        "channel": "#server-to-user-alice",
+                        "data": "hi",
+                    })
+                ),
+                other => panic!("expected the user event, got {:?}", other),
+            }
+        }
+        assert!(other.try_next().is_none());
+
+        // The socket that triggered the event is excluded
+        let event = user_event(json!({ "name": "notice", "data": "hi", "socket_id": "1.1" }));
+        let result = user_events(State(state), user_path("alice"), event).await;
+        assert!(result.is_ok());
+        assert!(first.try_next().is_none());
+        assert!(second.try_next().is_some());
+    }
+
+    #[tokio::test]
+    async fn user_events_take_no_channels() {
+        let state = state().await;
+        for event in [
+            json!({ "name": "notice", "data": "hi", "channel": "orders" }),
+            json!({ "name": "notice", "data": "hi", "channels": ["orders"] }),
+        ] {
+            let result =
+                user_events(State(state.clone()), user_path("alice"), user_event(event)).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    fn next_text(outbound: &OutboundQueue) -> Value {
+        match outbound.try_next() {
+            Some(OutboundMessage::Text(text)) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn terminating_a_user_closes_and_unsubscribes_its_sockets() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let (first, first_outbound) = signed_in(&app, "1.1", "alice").await;
+        let (second, second_outbound) = signed_in(&app, "1.2", "alice").await;
+        let (other, other_outbound) = signed_in(&app, "1.3", "bob").await;
+        for connection in [&first, &second, &other] {
+            join(&app, connection, "orders").await;
+            join(&app, connection, "presence-team").await;
+        }
+        while other_outbound.try_next().is_some() {}
+
+        let result = terminate_user_connections(State(state.clone()), user_path("alice")).await;
+        assert_eq!(body(result.ok().unwrap()).await, json!({}));
+
+        for outbound in [&first_outbound, &second_outbound] {
+            let error = next_text(outbound);
+            assert_eq!(error["event"], "pusher:error");
+            assert_eq!(error["data"]["code"], 4009);
+            match outbound.try_next() {
+                Some(OutboundMessage::Close { code, .. }) => assert_eq!(code, 4009),
+                other => panic!("expected a 4009 close, got {:?}", other),
+            }
+        }
+
+        // bob stays, and sees alice leave once her last socket is gone
+        let member_removed = next_text(&other_outbound);
+        assert_eq!(member_removed["event"], "pusher_internal:member_removed");
+        assert_eq!(member_removed["data"], json!({ "user_id": "alice" }));
+        assert!(other_outbound.try_next().is_none());
+        assert!(!other.is_closing());
+
+        let orders = app.channel_manager.get_channel("orders").await.unwrap();
+        assert_eq!(orders.unwrap().subscribers().await, vec!["1.3".to_string()]);
+        let team = app
+            .channel_manager
+            .get_channel("presence-team")
+            .await
+            .unwrap();
+        let users = team
+            .unwrap()
+            .as_presence()
+            .unwrap()
+            .get_presence_users()
+            .await;
+        let users: Vec<String> = users
+            .unwrap()
+            .into_iter()
+            .map(|user| user.user_id)
+            .collect();
+        assert_eq!(users, vec!["bob".to_string()]);
+
+        assert_eq!(app.connection_manager.connection_count().await, 1);
+        assert!(app
+            .connection_manager
+            .get_user_connections("alice")
+            .await
+            .is_empty());
+        // Adding a socket back does not find it under alice, so the user index was cleared too
+        app.connection_manager.add_connection(first).await;
+        assert!(app
+            .connection_manager
+            .get_user_connections("alice")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn terminating_an_unknown_user_does_nothing() {
+        let state = state().await;
+        let app = live_app(&state).await;
+        let (other, other_outbound) = signed_in(&app, "1.3", "bob").await;
+
+        let result = terminate_user_connections(State(state), user_path("alice")).await;
+        assert_eq!(body(result.ok().unwrap()).await, json!({}));
+        assert!(other_outbound.try_next().is_none());
+        assert!(!other.is_closing());
+    }
 }
@@ -1,93 +1,298 @@
-use crate::channel::{ChannelType, SafeChannelManager};
-use crate::connection::{Connection, SafeConnection, SafeConnectionManager};
+use crate::application::Application;
+use crate::auth::jwt::{is_jwt, JwtError, JwtVerifier};
+use crate::auth::rules::presence_channel_data;
+use crate::auth::{verify_auth_signature, verify_user_signature};
+use crate::channel::{server_to_user_id, ChannelType, PresenceChannel, PresenceUser};
+use crate::connection::{spawn_writer, BackpressurePolicy, ClientInfo, Connection, SafeConnection};
 
 use crate::error::AppError;
 use crate::log::Log;
-use crate::protocol::events::{PusherApiEventResponse, PusherEvent};
-use crate::protocol::messages::PusherMessage;
-use crate::websocket::WebSocket;
+use crate::protocol::close_codes::CloseCode;
+use crate::protocol::events::PusherApiEventResponse;
+use crate::protocol::messages::{
+    ClientEventMessage, PresenceChannelData, PusherMessage, SigninUserData,
+};
+use crate::webhook::WebhookEvent;
+use crate::websocket::{split, WebSocket};
+use futures::FutureExt;
 use rand::Rng;
-use serde_json::json;
+use serde_json::{json, Map, Value};
+use std::io;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use web_socket::Event;
 
-pub async fn handle_socket(
-    socket: WebSocket,
-    channel_manager: &SafeChannelManager,
-    connection_manager: &SafeConnectionManager,
-) {
+pub async fn handle_socket(socket: WebSocket, app: Arc<Application>, client_info: ClientInfo) {
+    let connection_manager = &app.connection_manager;
     let actual_connections = connection_manager.get_connections().await;
     Log::info("Existing connections:");
     for conn in actual_connections {
         Log::info(format!("Connection: {}", conn.socket_id));
     }
-    // get connections from all channels
-    let channels = channel_manager.get_channel("chat-room").await.unwrap();
-    match channels {
-        Some(channel) => {
-            let subscribers = channel.subscribers().await;
-            Log::info(format!("Subscribers: {:?}", subscribers));
+    Log::info("New WebSocket connection established");
+    let socket_id = generate_socket_id();
+    let (reader, writer) = split(socket);
+    let outbound = spawn_writer(writer, app.max_buffer_size, app.backpressure_policy);
+    let connection = Connection::new(socket_id.clone(), client_info, outbound);
+
+    let refusal = if !app.enabled {
+        Some((CloseCode::AppDisabled, "App is disabled"))
+    } else if !connection_manager
+        .try_add_connection(connection.clone(), app.max_connections)
+        .await
+    {
+        Some((CloseCode::OverConnectionQuota, "Over connection quota"))
+    } else {
+        None
+    };
+    if let Some((code, message)) = refusal {
+        Log::warning(format!(
+            "Refusing connection to app {}: {}",
+            app.app_id, message
+        ));
+        refuse(&connection, code, message);
+        return;
+    }
+
+    Log::info(format!(
+        "New connection established: {} from {}",
+        socket_id, connection.client_info
+    ));
+
+    // Whatever ends the read loop, the connection must leave the manager and its channels
+    match AssertUnwindSafe(read_loop(reader, &connection, &app))
+        .catch_unwind()
+        .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            Log::error(format!("Connection {} failed: {}", socket_id, e));
+            let _ = connection.close(CloseCode::GenericReconnect, "Internal server error");
         }
-        None => {
-            Log::info("No subscribers");
+        Err(_) => {
+            Log::error(format!("Connection {} handler panicked", socket_id));
+            let _ = connection.close(CloseCode::GenericReconnect, "Internal server error");
         }
     }
-    Log::info("New WebSocket connection established");
-    let socket_id = generate_socket_id();
-    let connection = Connection::new(socket_id.clone(), socket);
-    connection_manager.add_connection(connection.clone()).await;
+    cleanup_connection(&connection, &app).await;
 
-    Log::info(format!("New connection established: {}", socket_id));
+    Log::websocket_title("❌ Connection closed:");
+    Log::info(format!("Socket ID: {}", socket_id));
+    if connection.dropped_messages() > 0 {
+        Log::warning(format!(
+            "Dropped {} messages for slow client {} ({} still queued)",
+            connection.dropped_messages(),
+            socket_id,
+            connection.buffer_depth()
+        ));
+    }
+}
+
+/// Reads frames in a task of its own and hands them over one at a time.
+///
+/// A frame is read in several steps, so a read that was given up on, e.g. by
+/// the activity timeout, would lose the bytes it had consumed and leave the
+/// rest of the stream unreadable. Waiting on the channel instead can be given
+/// up on safely.
+fn spawn_reader<R>(
+    mut reader: web_socket::WebSocket<R>,
+) -> (mpsc::Receiver<io::Result<Event>>, AbortOnDrop)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (frames, received) = mpsc::channel(1);
+    let task = tokio::spawn(async move {
+        loop {
+            let frame = reader.recv().await;
+            let last = matches!(frame, Err(_) | Ok(Event::Close { .. }));
+            if frames.send(frame).await.is_err() || last {
+                break;
+            }
+        }
+    });
+    (received, AbortOnDrop(task))
+}
+
+/// Stops the reader task with the read loop, even when that panics.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
 
-    // Send connection established message
+async fn read_loop<R>(
+    reader: web_socket::WebSocket<R>,
+    connection: &SafeConnection,
+    app: &Application,
+) -> Result<(), AppError>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (mut frames, _reader_task) = spawn_reader(reader);
     let conn_established = PusherMessage::ConnectionEstablished {
-        socket_id: socket_id.clone(),
-        activity_timeout: 120,
+        socket_id: connection.socket_id.clone(),
+        activity_timeout: app.activity_timeout as u32,
     };
-    connection
-        .send_message(serde_json::to_string(&conn_established).unwrap())
-        .await;
+    connection.send_message(serde_json::to_string(&conn_established)?)?;
+
+    let activity_timeout = Duration::from_secs(app.activity_timeout);
+    let pong_timeout = Duration::from_secs(app.pong_timeout);
+    let mut awaiting_pong = false;
+    loop {
+        let wait = if awaiting_pong {
+            pong_timeout
+        } else {
+            activity_timeout.saturating_sub(connection.idle_for().await)
+        };
+        let received = tokio::select! {
+            received = tokio::time::timeout(wait, frames.recv()) => received,
+            _ = connection.over_capacity() => return Ok(()),
+        };
+        let ev = match received {
+            Ok(Some(Ok(ev))) => ev,
+            // The client went away without a close frame
+            Ok(Some(Err(e))) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Some(Err(e))) => return Err(AppError::IoError(e)),
+            Ok(None) => return Ok(()),
+            Err(_) if awaiting_pong => {
+                Log::warning(format!(
+                    "No pong from {}, closing connection",
+                    connection.socket_id
+                ));
+                return connection.close(CloseCode::PongNotReceived, "Pong reply not received");
+            }
+            Err(_) => {
+                let ping = PusherMessage::Ping {
+                    data: Some(json!({})),
+                };
+                connection.send_message(serde_json::to_string(&ping)?)?;
+                awaiting_pong = true;
+                continue;
+            }
+        };
+        connection.touch().await;
+        awaiting_pong = false;
 
-    while let Ok(ev) = connection.recv().await {
         match ev {
+            // Once the connection is closing, e.g. terminated by the app, nothing
+            // the client asks for may subscribe or sign it in again
+            Event::Data { .. } if connection.is_closing() => {}
             Event::Data { data, .. } => {
-                let message = String::from_utf8(data.to_vec())
-                    .map_err(|e| AppError::BadRequest(format!("Invalid message format: {}", e)))
-                    .unwrap();
-                handle_client_message(message, &connection, channel_manager)
-                    .await
-                    .expect("TODO: panic message");
+                let handled = match std::str::from_utf8(&data) {
+                    Ok(message) => {
+                        handle_client_message(message.to_string(), connection, app).await
+                    }
+                    Err(e) => Err(AppError::BadRequest(format!(
+                        "Invalid message format: {}",
+                        e
+                    ))),
+                };
+                match handled {
+                    Ok(()) => {}
+                    // The socket is going away, so there is nobody left to tell
+                    Err(e @ AppError::ConnectionError(_)) => return Err(e),
+                    Err(e) => {
+                        Log::warning(format!(
+                            "Rejected message from {}: {}",
+                            connection.socket_id, e
+                        ));
+                        send_error(connection, &e)?;
+                    }
+                }
             }
             Event::Ping(_) => {}
             Event::Pong(_) => {}
-            Event::Error(_) => {
-                Log::error("Error event received");
+            Event::Error(e) => {
+                Log::error(format!("Protocol error on {}: {}", connection.socket_id, e));
             }
             Event::Close { code, reason } => {
-                // write the code and reason to the log
-                break;
+                Log::info(format!(
+                    "Client {} closed the connection: {} {}",
+                    connection.socket_id, code, reason
+                ));
+                // Fails only when this is the answer to our own close frame
+                let _ = connection.acknowledge_close(code);
+                return Ok(());
             }
         }
     }
-    connection_manager.remove_connection(&socket_id).await;
-    let subscribed_channels = { connection.get_subscribed_channels().await.clone() };
-    for channel_name in subscribed_channels {
-        if let Some(channel) = channel_manager.get_channel(&channel_name).await.unwrap()
-        {
-            channel.unsubscribe(&socket_id).await.unwrap();
+}
+
+/// Answers an upgraded socket that cannot be served with a `pusher:error` and a close frame.
+pub async fn refuse_socket(socket: WebSocket, code: CloseCode, message: String) {
+    let (_reader, writer) = split(socket);
+    let outbound = spawn_writer(writer, 2, BackpressurePolicy::DropNewest);
+    let connection = Connection::new(generate_socket_id(), ClientInfo::default(), outbound);
+    refuse(&connection, code, &message);
+}
+
+fn refuse(connection: &SafeConnection, code: CloseCode, message: &str) {
+    let error = PusherMessage::Error {
+        code: Some(code.code().into()),
+        message: message.to_string(),
+    };
+    if let Ok(error) = serde_json::to_string(&error) {
+        let _ = connection.send_message(error);
+    }
+    let _ = connection.close(code, message);
+}
+
+/// Closes a connection on behalf of the app and removes it from its channels
+/// right away instead of waiting for the client to acknowledge the close.
+pub async fn terminate_connection(
+    connection: &SafeConnection,
+    app: &Application,
+    code: CloseCode,
+    message: &str,
+) {
+    refuse(connection, code, message);
+    cleanup_connection(connection, app).await;
+}
+
+/// Removes a connection from its app and from every channel it joined.
+async fn cleanup_connection(connection: &SafeConnection, app: &Application) {
+    app.connection_manager.remove_connection(connection).await;
+    for channel_name in connection.get_subscribed_channels().await {
+        if let Err(e) = handle_unsubscribe(channel_name, connection, app).await {
+            Log::error(format!(
+                "Failed to unsubscribe {}: {}",
+                connection.socket_id, e
+            ));
         }
     }
-    Log::websocket_title("❌ Connection closed:");
-    Log::info(format!("Socket ID: {}", socket_id));
-    connection.close("inchis").await;
+}
+
+/// Reports a rejected client frame as a `pusher:error` instead of dropping the connection.
+fn send_error(connection: &SafeConnection, error: &AppError) -> Result<(), AppError> {
+    let error = PusherMessage::Error {
+        code: Some(error.pusher_code()),
+        message: error.to_string(),
+    };
+    connection.send_message(serde_json::to_string(&error)?)
 }
 
 async fn handle_client_message(
     message: String,
     connection: &SafeConnection,
-    channel_manager: &SafeChannelManager,
+    app: &Application,
 ) -> Result<(), AppError> {
     Log::info(format!("Received message: {:?}", message.clone()));
-    let pusher_message: PusherMessage = serde_json::from_str(&message)
+    let message: Value = serde_json::from_str(&message)
+        .map_err(|e| AppError::BadRequest(format!("Invalid message format: {}", e)))?;
+
+    if ClientEventMessage::is_client_event(&message) {
+        let client_event: ClientEventMessage = serde_json::from_value(message)
+            .map_err(|e| AppError::BadRequest(format!("Invalid client event: {}", e)))?;
+        return handle_client_event(client_event, connection, app).await;
+    }
+
+    let pusher_message: PusherMessage = serde_json::from_value(message)
         .map_err(|e| AppError::BadRequest(format!("Invalid message format: {}", e)))?;
 
     match pusher_message {
@@ -96,25 +301,18 @@ async fn handle_client_message(
             auth,
             channel_data,
         } => {
-            handle_subscribe(channel, connection, channel_manager).await?;
+            handle_subscribe(channel, auth, channel_data, connection, app).await?;
         }
         PusherMessage::Unsubscribe { channel } => {
-            connection.subscribed_channels.lock().await.remove(&channel);
-            handle_unsubscribe(channel, connection, channel_manager).await?;
+            handle_unsubscribe(channel, connection, app).await?;
         }
-        PusherMessage::Ping { data } => {
-            connection
-                .send_message(serde_json::to_string(&PusherMessage::Pong {
-                    data: Some(json!({})),
-                })?)
-                .await;
+        PusherMessage::Signin { auth, user_data } => {
+            handle_signin(auth, user_data, connection, app).await?;
         }
-        PusherMessage::ClientEvent {
-            channel,
-            event,
-            data,
-        } => {
-            handle_client_event(channel, event, data, connection, channel_manager).await?;
+        PusherMessage::Ping { .. } => {
+            connection.send_message(serde_json::to_string(&PusherMessage::Pong {
+                data: Some(json!({})),
+            })?)?;
         }
         _ => {
             // Ignore other message types
@@ -124,106 +322,398 @@ async fn handle_client_message(
     Ok(())
 }
 
+/// Signs the connection in as the user described by `user_data` once its
+/// signature checks out, so events sent to that user reach this socket.
+async fn handle_signin(
+    auth: String,
+    user_data: String,
+    connection: &SafeConnection,
+    app: &Application,
+) -> Result<(), AppError> {
+    if !verify_user_signature(
+        &app.key,
+        &app.secret,
+        &auth,
+        &connection.socket_id,
+        &user_data,
+    ) {
+        Log::warning(format!(
+            "Rejected signin of {}: invalid signature",
+            connection.socket_id
+        ));
+        return Err(AppError::AuthenticationError("Invalid signature".into()));
+    }
+    let user_info: Value = serde_json::from_str(&user_data)
+        .map_err(|e| AppError::BadRequest(format!("Invalid user data: {}", e)))?;
+    let user: SigninUserData = serde_json::from_value(user_info.clone())
+        .map_err(|e| AppError::BadRequest(format!("Invalid user data: {}", e)))?;
+
+    let previous = connection.user_id.lock().await.clone();
+    if let Some(previous) = previous.filter(|previous| *previous != user.id) {
+        app.connection_manager
+            .remove_user(&connection.socket_id, &previous)
+            .await;
+    }
+    connection.set_user_id(user.id.clone()).await;
+    connection.set_user_data(user_info).await;
+    connection.rule_decisions.lock().await.clear();
+    app.connection_manager
+        .add_user(&connection.socket_id, &user.id)
+        .await;
+    Log::info(format!(
+        "Signed in {} as user {}",
+        connection.socket_id, user.id
+    ));
+
+    connection.send_message(serde_json::to_string(&PusherMessage::SigninSuccess {
+        user_data,
+    })?)
+}
+
 async fn handle_subscribe(
     channel_name: String,
+    auth: Option<String>,
+    mut channel_data: Option<String>,
     connection: &SafeConnection,
-    channel_manager: &SafeChannelManager,
+    app: &Application,
 ) -> Result<(), AppError> {
+    if channel_name.starts_with('#') {
+        return handle_server_channel_subscribe(channel_name, connection).await;
+    }
+
     let channel_type = determine_channel_type(&channel_name);
-    let channel = channel_manager
-        .create_channel(channel_name.clone(), channel_type)
-        .await
-        .unwrap();
 
-    channel
-        .subscribe(connection)
-        .await
-        .expect("TODO: panic message");
+    let ruling = match &app.channel_rules {
+        Some(rules) if channel_type != ChannelType::Public => {
+            rules.evaluate_for(&channel_name, connection).await
+        }
+        _ => None,
+    };
+    if ruling == Some(false) {
+        Log::warning(format!(
+            "Rejected subscription of {} to {}: denied by the channel rules",
+            connection.socket_id, channel_name
+        ));
+        return send_subscription_error(
+            connection,
+            channel_name,
+            "AuthError",
+            "Not allowed by the channel rules",
+            403,
+        )
+        .await;
+    }
+    // A channel the rules allow needs neither a signature nor a token, even
+    // when the app requires tokens
+    if ruling == Some(true) {
+        // The member is whoever the connection signed in as
+        if channel_type == ChannelType::Presence {
+            channel_data = presence_channel_data(connection).await;
+        }
+    } else if let (Some(verifier), Some(token), false) = (
+        &app.jwt,
+        auth.as_deref().filter(|auth| is_jwt(auth)),
+        channel_type == ChannelType::Public,
+    ) {
+        match verifier.verify(token, &channel_name) {
+            Ok(grant) => {
+                if channel_type == ChannelType::Presence {
+                    channel_data = grant.presence_channel_data();
+                }
+            }
+            Err(e) => {
+                Log::warning(format!(
+                    "Rejected subscription of {} to {}: {}",
+                    connection.socket_id, channel_name, e
+                ));
+                let status = match e {
+                    JwtError::Invalid(_) => 401,
+                    JwtError::ChannelNotAllowed(_) => 403,
+                };
+                return send_subscription_error(
+                    connection,
+                    channel_name,
+                    "AuthError",
+                    &e.to_string(),
+                    status,
+                )
+                .await;
+            }
+        }
+    } else if channel_type != ChannelType::Public {
+        if app.jwt.as_ref().is_some_and(JwtVerifier::required) {
+            Log::warning(format!(
+                "Rejected subscription of {} to {}: no token",
+                connection.socket_id, channel_name
+            ));
+            return send_subscription_error(
+                connection,
+                channel_name,
+                "AuthError",
+                "A JWT is required",
+                401,
+            )
+            .await;
+        }
+        let authorized = auth.as_deref().is_some_and(|auth| {
+            verify_auth_signature(
+                &app.key,
+                &app.secret,
+                auth,
+                &connection.socket_id,
+                &channel_name,
+                channel_data.as_deref(),
+            )
+        });
+        if !authorized {
+            Log::warning(format!(
+                "Rejected subscription of {} to {}: invalid signature",
+                connection.socket_id, channel_name
+            ));
+            return send_subscription_error(
+                connection,
+                channel_name,
+                "AuthError",
+                "Invalid signature",
+                401,
+            )
+            .await;
+        }
+    }
+
+    let presence_data = if channel_type == ChannelType::Presence {
+        match channel_data
+            .as_deref()
+            .map(serde_json::from_str::<PresenceChannelData>)
+        {
+            Some(Ok(presence_data)) => Some(presence_data),
+            _ => {
+                return send_subscription_error(
+                    connection,
+                    channel_name,
+                    "AuthError",
+                    "Invalid presence channel data",
+                    400,
+                )
+                .await;
+            }
+        }
+    } else {
+        None
+    };
+
+    let member = presence_data.map(|presence_data| PresenceUser {
+        user_id: presence_data.user_id,
+        user_info: presence_data.user_info,
+    });
+    let (channel, joined) = app
+        .subscribe(&channel_name, channel_type, connection, member.clone())
+        .await?;
     connection.subscribe(channel_name.clone()).await;
-    // For presence channels, you'd add presence data here
+
+    let subscription_data = match (channel.as_presence(), member) {
+        (Some(presence), Some(user)) => {
+            if joined.new_member {
+                let member_added = PusherApiEventResponse {
+                    event: "pusher_internal:member_added".to_string(),
+                    channel: channel_name.clone(),
+                    data: Some(json!({
+                        "user_id": user.user_id,
+                        "user_info": user.user_info,
+                    })),
+                };
+                channel
+                    .broadcast(
+                        serde_json::to_string(&member_added)?,
+                        Some(&connection.socket_id),
+                    )
+                    .await?;
+            }
+            presence_hash(presence).await?
+        }
+        _ => json!({}),
+    };
 
+    let subscription_succeeded = PusherApiEventResponse {
+        event: "pusher_internal:subscription_succeeded".to_string(),
+        channel: channel_name.clone(),
+        data: Some(subscription_data),
+    };
+    connection.send_message(serde_json::to_string(&subscription_succeeded)?)
+}
+
+/// `#` channels belong to the server. The only one a client may subscribe to is
+/// `#server-to-user-{id}` of the user it signed in as; its events are delivered
+/// through the user index, so the channel is not registered anywhere.
+async fn handle_server_channel_subscribe(
+    channel_name: String,
+    connection: &SafeConnection,
+) -> Result<(), AppError> {
+    let user_id = connection.user_id.lock().await.clone();
+    if user_id.is_none() || server_to_user_id(&channel_name) != user_id.as_deref() {
+        return send_subscription_error(
+            connection,
+            channel_name,
+            "AuthError",
+            "Not signed in as the user of this channel",
+            403,
+        )
+        .await;
+    }
     let subscription_succeeded = PusherApiEventResponse {
         event: "pusher_internal:subscription_succeeded".to_string(),
         channel: channel_name,
         data: Some(json!({})),
     };
-    connection
-        .send_message(serde_json::to_string(&subscription_succeeded)?)
-        .await;
+    connection.send_message(serde_json::to_string(&subscription_succeeded)?)
+}
 
-    Ok(())
+/// Builds the `{"presence": {"ids", "hash", "count"}}` payload of a presence subscription.
+async fn presence_hash(presence: &dyn PresenceChannel) -> Result<Value, AppError> {
+    let users = presence.get_presence_users().await?;
+    let ids: Vec<&str> = users.iter().map(|user| user.user_id.as_str()).collect();
+    let hash: Map<String, Value> = users
+        .iter()
+        .map(|user| (user.user_id.clone(), user.user_info.clone()))
+        .collect();
+    Ok(json!({
+        "presence": {
+            "ids": ids,
+            "hash": hash,
+            "count": users.len(),
+        }
+    }))
+}
+
+async fn send_subscription_error(
+    connection: &SafeConnection,
+    channel_name: String,
+    error_type: &str,
+    error: &str,
+    status: u16,
+) -> Result<(), AppError> {
+    let subscription_error = PusherApiEventResponse {
+        event: "pusher:subscription_error".to_string(),
+        channel: channel_name,
+        data: Some(json!({
+            "type": error_type,
+            "error": error,
+            "status": status,
+        })),
+    };
+    connection.send_message(serde_json::to_string(&subscription_error)?)
 }
 
+/// Leaves a channel the connection joined. Only the first call per channel has any
+/// effect, so overlapping cleanups of the same connection are harmless.
 async fn handle_unsubscribe(
     channel_name: String,
     connection: &SafeConnection,
-    channel_manager: &SafeChannelManager,
+    app: &Application,
 ) -> Result<(), AppError> {
-    if let Some(channel) = channel_manager.get_channel(&channel_name).await.unwrap() {
-        channel.unsubscribe(&connection.socket_id).await.unwrap();
-        connection.unsubscribe(&channel_name).await;
+    if !connection.unsubscribe(&channel_name).await {
+        return Ok(());
+    }
+    let Some((channel, left)) = app
+        .unsubscribe(&channel_name, &connection.socket_id)
+        .await?
+    else {
+        return Ok(());
+    };
+    if let Some(user) = left.removed_member {
+        let member_removed = PusherApiEventResponse {
+            event: "pusher_internal:member_removed".to_string(),
+            channel: channel_name.clone(),
+            data: Some(json!({ "user_id": user.user_id })),
+        };
+        channel
+            .broadcast(
+                serde_json::to_string(&member_removed)?,
+                Some(&connection.socket_id),
+            )
+            .await?;
     }
     Ok(())
 }
 
 async fn handle_client_event(
-    channel_name: String,
-    event: String,
-    data: serde_json::Value,
+    mut client_event: ClientEventMessage,
     connection: &SafeConnection,
-    channel_manager: &SafeChannelManager,
+    app: &Application,
 ) -> Result<(), AppError> {
-    // Verify that client events are allowed for this channel
-    if !channel_name.starts_with("private-") && !channel_name.starts_with("presence-") {
-        return Err(AppError::BadRequest(
-            "Client events are only allowed on private or presence channels".into(),
-        ));
+    let channel_manager = &app.channel_manager;
+    let channel_name = client_event.channel.clone();
+
+    if !app.enable_client_messages {
+        return send_client_event_error(connection, "Client events are not enabled for this app")
+            .await;
     }
 
-    let channel = channel_manager.get_channel(&channel_name).await;
+    // Verify that client events are allowed for this channel
+    let channel_type = determine_channel_type(&channel_name);
+    if channel_type == ChannelType::Public || channel_name.starts_with("private-encrypted-") {
+        return send_client_event_error(
+            connection,
+            "Client events are only allowed on private or presence channels",
+        )
+        .await;
+    }
+    if !connection
+        .get_subscribed_channels()
+        .await
+        .contains(&channel_name)
+    {
+        return send_client_event_error(
+            connection,
+            &format!("Client is not subscribed to {}", channel_name),
+        )
+        .await;
+    }
+    let Some(channel) = channel_manager.get_channel(&channel_name).await? else {
+        return send_client_event_error(
+            connection,
+            &format!("Client is not subscribed to {}", channel_name),
+        )
+        .await;
+    };
 
-    match channel {
-        Ok(channel) => match channel {
-            Some(channel) => {
-                let client_event = PusherEvent::ClientEvent {
-                    channel: channel_name,
-                    event,
-                    data,
-                };
-                match channel
-                    .broadcast(serde_json::to_string(&client_event)?)
-                    .await
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err(AppError::InternalServerError(format!(
-                            "Failed to broadcast event: {}",
-                            e
-                        )));
-                    }
-                }
-            }
-            None => {
-                return Err(AppError::BadRequest("Channel not found".into()));
-            }
-        },
-        Err(_) => {
-            return Err(AppError::BadRequest("Error".into()));
-        }
+    if let Some(presence) = channel.as_presence() {
+        client_event.user_id = presence
+            .get_presence_user(&connection.socket_id)
+            .await?
+            .map(|user| user.user_id);
     }
 
+    channel
+        .broadcast(
+            serde_json::to_string(&client_event)?,
+            Some(&connection.socket_id),
+        )
+        .await?;
+    app.send_webhook(WebhookEvent::client_event(
+        &channel_name,
+        &client_event.event,
+        client_event.data,
+        &connection.socket_id,
+        client_event.user_id,
+    ));
+
     Ok(())
 }
 
-async fn send_message(socket: &mut WebSocket, message: PusherMessage) -> Result<(), AppError> {
-    let message_str = serde_json::to_string(&message).map_err(|e| {
-        AppError::InternalServerError(format!("Failed to serialize message: {}", e))
-    })?;
-    socket
-        .send(message_str.as_bytes())
-        .await
-        .expect("TODO: panic message");
-    Ok(())
+async fn send_client_event_error(
+    connection: &SafeConnection,
+    message: &str,
+) -> Result<(), AppError> {
+    Log::warning(format!(
+        "Rejected client event from {}: {}",
+        connection.socket_id, message
+    ));
+    let error = PusherMessage::Error {
+        code: Some(4301),
+        message: message.to_string(),
+    };
+    connection.send_message(serde_json::to_string(&error)?)
 }
 
 fn determine_channel_type(channel_name: &str) -> ChannelType {
@@ -244,3 +734,332 @@ fn generate_socket_id() -> String {
 
     format!("{}.{}", random_number(min, max), random_number(min, max))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::rules::ChannelRules;
+    use crate::auth::{generate_auth_signature, sign};
+    use crate::connection::{OutboundMessage, OutboundQueue};
+    use tokio::io::DuplexStream;
+
+    fn app() -> Application {
+        Application::new("1".into(), "key".into(), "secret".into())
+    }
+
+    /// A connection together with the queue its writer task would drain.
+    fn client(socket_id: &str) -> (SafeConnection, Arc<OutboundQueue>) {
+        let outbound = OutboundQueue::new(16, BackpressurePolicy::DropNewest);
+        let connection = Connection::new(
+            socket_id.to_string(),
+            ClientInfo::default(),
+            outbound.clone(),
+        );
+        (connection, outbound)
+    }
+
+    fn next_frame(outbound: &OutboundQueue) -> Value {
+        match outbound.try_next() {
+            Some(OutboundMessage::Text(text)) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text frame, got {:?}", other),
+        }
+    }
+
+    fn next_event(outbound: &OutboundQueue) -> String {
+        next_frame(outbound)["event"].as_str().unwrap().to_string()
+    }
+
+    /// Subscribes with a valid signature for `channel` and `channel_data`.
+    async fn subscribe_signed(
+        connection: &SafeConnection,
+        outbound: &OutboundQueue,
+        app: &Application,
+        channel: &str,
+        channel_data: Option<Value>,
+    ) {
+        let channel_data = channel_data.map(|data| data.to_string());
+        let auth = generate_auth_signature(
+            &app.key,
+            &app.secret,
+            &connection.socket_id,
+            channel,
+            channel_data.as_deref(),
+        );
+        let mut data = json!({ "channel": channel, "auth": auth });
+        if let Some(channel_data) = channel_data {
+            data["channel_data"] = channel_data.into();
+        }
+        let subscribe = json!({ "event": "pusher:subscribe", "data": data });
+        handle_client_message(subscribe.to_string(), connection, app)
+            .await
+            .unwrap();
+        assert_eq!(
+            next_event(outbound),
+            "pusher_internal:subscription_succeeded"
+        );
+    }
+
+    async fn send_client_event(connection: &SafeConnection, app: &Application, channel: &str) {
+        let event = json!({
+            "event": "client-typing",
+            "channel": channel,
+            "data": { "typing": true },
+        });
+        handle_client_message(event.to_string(), connection, app)
+            .await
+            .unwrap();
+    }
+
+    fn assert_client_event_error(outbound: &OutboundQueue) {
+        let frame = next_frame(outbound);
+        assert_eq!(frame["event"], "pusher:error");
+        assert_eq!(frame["data"]["code"], 4301);
+    }
+
+    async fn signin(
+        connection: &SafeConnection,
+        outbound: &OutboundQueue,
+        app: &Application,
+        user_data: Value,
+    ) {
+        let user_data = user_data.to_string();
+        let signature = sign(
+            &app.secret,
+            &format!("{}::user::{}", connection.socket_id, user_data),
+        );
+        let signin = json!({
+            "event": "pusher:signin",
+            "data": { "auth": format!("{}:{}", app.key, signature), "user_data": user_data },
+        });
+        handle_client_message(signin.to_string(), connection, app)
+            .await
+            .unwrap();
+        assert_eq!(next_event(outbound), "pusher:signin_success");
+    }
+
+    async fn subscribe(
+        connection: &SafeConnection,
+        outbound: &OutboundQueue,
+        app: &Application,
+        channel: &str,
+    ) -> String {
+        let subscribe = json!({ "event": "pusher:subscribe", "data": { "channel": channel } });
+        handle_client_message(subscribe.to_string(), connection, app)
+            .await
+            .unwrap();
+        next_event(outbound)
+    }
+
+    #[tokio::test]
+    async fn signing_in_again_clears_cached_rule_decisions() {
+        let mut app = app();
+        app.channel_rules = Some(
+            ChannelRules::parse(
+                "[[rules]]\nchannel = \"private-team.{team}\"\nwhen = [\"team in auth.user_data.teams\"]",
+            )
+            .unwrap(),
+        );
+        let (connection, outbound) = client("1.1");
+
+        signin(
+            &connection,
+            &outbound,
+            &app,
+            json!({ "id": "7", "teams": ["red"] }),
+        )
+        .await;
+        assert_eq!(
+            subscribe(&connection, &outbound, &app, "private-team.red").await,
+            "pusher_internal:subscription_succeeded"
+        );
+        handle_unsubscribe("private-team.red".to_string(), &connection, &app)
+            .await
+            .unwrap();
+
+        signin(
+            &connection,
+            &outbound,
+            &app,
+            json!({ "id": "7", "teams": [] }),
+        )
+        .await;
+        assert_eq!(
+            subscribe(&connection, &outbound, &app, "private-team.red").await,
+            "pusher:subscription_error"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_events_need_a_subscription() {
+        let app = app();
+        let (connection, outbound) = client("1.1");
+        send_client_event(&connection, &app, "private-chat").await;
+        assert_client_event_error(&outbound);
+    }
+
+    #[tokio::test]
+    async fn client_events_are_refused_on_public_channels() {
+        let app = app();
+        let (connection, outbound) = client("1.1");
+        assert_eq!(
+            subscribe(&connection, &outbound, &app, "chat").await,
+            "pusher_internal:subscription_succeeded"
+        );
+        send_client_event(&connection, &app, "chat").await;
+        assert_client_event_error(&outbound);
+    }
+
+    #[tokio::test]
+    async fn client_events_are_refused_when_disabled() {
+        let mut app = app();
+        app.enable_client_messages = false;
+        let (connection, outbound) = client("1.1");
+        subscribe_signed(&connection, &outbound, &app, "private-chat", None).await;
+        send_client_event(&connection, &app, "private-chat").await;
+        assert_client_event_error(&outbound);
+    }
+
+    #[tokio::test]
+    async fn client_events_reach_the_other_subscribers_in_wire_format() {
+        let app = app();
+        let (sender, sender_outbound) = client("1.1");
+        let (receiver, receiver_outbound) = client("2.2");
+        subscribe_signed(&sender, &sender_outbound, &app, "private-chat", None).await;
+        subscribe_signed(&receiver, &receiver_outbound, &app, "private-chat", None).await;
+
+        send_client_event(&sender, &app, "private-chat").await;
+        assert_eq!(
+            next_frame(&receiver_outbound),
+            json!({
+                "event": "client-typing",
+                "channel": "private-chat",
+                "data": { "typing": true },
+            })
+        );
+        assert!(sender_outbound.try_next().is_none());
+    }
+
+    #[tokio::test]
+    async fn presence_client_events_carry_the_user_id() {
+        let app = app();
+        let (sender, sender_outbound) = client("1.1");
+        let (receiver, receiver_outbound) = client("2.2");
+        subscribe_signed(
+            &sender,
+            &sender_outbound,
+            &app,
+            "presence-room",
+            Some(json!({ "user_id": "7" })),
+        )
+        .await;
+        subscribe_signed(
+            &receiver,
+            &receiver_outbound,
+            &app,
+            "presence-room",
+            Some(json!({ "user_id": "8" })),
+        )
+        .await;
+        assert_eq!(next_event(&sender_outbound), "pusher_internal:member_added");
+
+        send_client_event(&sender, &app, "presence-room").await;
+        assert_eq!(
+            next_frame(&receiver_outbound),
+            json!({
+                "event": "client-typing",
+                "channel": "presence-room",
+                "data": { "typing": true },
+                "user_id": "7",
+            })
+        );
+        assert!(sender_outbound.try_next().is_none());
+    }
+
+    /// Runs the read loop of `connection` on an in-memory socket and returns the client end.
+    fn serve(
+        app: Application,
+        connection: SafeConnection,
+    ) -> (
+        web_socket::WebSocket<DuplexStream>,
+        JoinHandle<Result<(), AppError>>,
+    ) {
+        let (server, client) = tokio::io::duplex(1024);
+        let reader = web_socket::WebSocket::server(server);
+        let read_loop = tokio::spawn(async move { read_loop(reader, &connection, &app).await });
+        (web_socket::WebSocket::client(client), read_loop)
+    }
+
+    async fn advance(secs: u64) {
+        tokio::time::sleep(Duration::from_secs(secs)).await;
+    }
+
+    fn assert_closed_for_missing_pong(outbound: &OutboundQueue) {
+        match outbound.try_next() {
+            Some(OutboundMessage::Close { code, .. }) => {
+                assert_eq!(code, CloseCode::PongNotReceived.code())
+            }
+            other => panic!("expected a 4201 close, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_clients_are_pinged_and_closed_without_a_pong() {
+        let (connection, outbound) = client("1.1");
+        let (_client, read_loop) = serve(app().with_timeouts(10, 3), connection);
+
+        advance(9).await;
+        assert_eq!(next_event(&outbound), "pusher:connection_established");
+        assert!(outbound.try_next().is_none());
+
+        advance(2).await;
+        assert_eq!(next_event(&outbound), "pusher:ping");
+        advance(1).await;
+        assert!(outbound.try_next().is_none());
+
+        advance(2).await;
+        assert_closed_for_missing_pong(&outbound);
+        read_loop.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn any_frame_resets_the_inactivity_timer() {
+        let (connection, outbound) = client("1.1");
+        let (mut client, read_loop) = serve(app().with_timeouts(10, 3), connection);
+
+        advance(8).await;
+        assert_eq!(next_event(&outbound), "pusher:connection_established");
+        client.send_ping("").await.unwrap();
+        advance(8).await;
+        assert!(outbound.try_next().is_none());
+        advance(3).await;
+        assert_eq!(next_event(&outbound), "pusher:ping");
+
+        // Answering the ping restarts the activity timeout instead of the pong timeout
+        client
+            .send(r#"{"event":"pusher:pong","data":{}}"#)
+            .await
+            .unwrap();
+        advance(4).await;
+        assert!(outbound.try_next().is_none());
+        advance(7).await;
+        assert_eq!(next_event(&outbound), "pusher:ping");
+        advance(4).await;
+        assert_closed_for_missing_pong(&outbound);
+        read_loop.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_read_loop_uses_the_app_timeouts() {
+        let (connection, outbound) = client("1.1");
+        let (_client, read_loop) = serve(app().with_timeouts(5, 2), connection);
+
+        advance(4).await;
+        assert_eq!(next_event(&outbound), "pusher:connection_established");
+        assert!(outbound.try_next().is_none());
+        advance(2).await;
+        assert_eq!(next_event(&outbound), "pusher:ping");
+        advance(2).await;
+        assert_closed_for_missing_pong(&outbound);
+        read_loop.await.unwrap().unwrap();
+    }
+}
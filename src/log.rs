@@ -1,16 +1,41 @@
 use colored::*;
 use chrono::Local;
+use serde::Deserialize;
 use serde_json::Value;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Most verbose level that gets printed, ordered from quietest to loudest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
 
 pub struct Log;
 
 impl Log {
+    pub fn set_level(level: LogLevel) {
+        LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    }
+
+    fn enabled(level: LogLevel) -> bool {
+        level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+    }
+
     pub fn info<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["cyan"], 2, 0);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["cyan"], 2, 0);
+        }
     }
 
     pub fn success<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["green"], 2, 0);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["green"], 2, 0);
+        }
     }
 
     pub fn error<T: AsRef<str>>(message: T) {
@@ -18,11 +43,15 @@ impl Log {
     }
 
     pub fn warning<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["yellow"], 2, 0);
+        if Self::enabled(LogLevel::Warn) {
+            Self::log_auto(message, &["yellow"], 2, 0);
+        }
     }
 
     pub fn cluster<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["bold", "magenta"], 2, 0);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["bold", "magenta"], 2, 0);
+        }
     }
 
     pub fn http<T: AsRef<str>>(message: T) {
@@ -30,7 +59,9 @@ impl Log {
     }
 
     pub fn discover<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["bold", "bright_cyan"], 2, 0);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["bold", "bright_cyan"], 2, 0);
+        }
     }
 
     pub fn websocket<T: AsRef<str>>(message: T) {
@@ -38,15 +69,21 @@ impl Log {
     }
 
     pub fn webhook_sender<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["bold", "white"], 2, 0);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["bold", "white"], 2, 0);
+        }
     }
 
     pub fn info_title<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["bold", "on_cyan"], 2, 1);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["bold", "on_cyan"], 2, 1);
+        }
     }
 
     pub fn success_title<T: AsRef<str>>(message: T) {
-        Self::log_auto(message, &["bold", "on_green"], 2, 1);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(message, &["bold", "on_green"], 2, 1);
+        }
     }
 
     pub fn error_title<T: AsRef<str>>(message: T) {
@@ -54,11 +91,15 @@ impl Log {
     }
 
     pub fn warning_title<T: AsRef<str>>(message: T) {
-        Self::log_auto(Self::prefix_with_time(message), &["bold", "on_yellow"], 2, 1);
+        if Self::enabled(LogLevel::Warn) {
+            Self::log_auto(Self::prefix_with_time(message), &["bold", "on_yellow"], 2, 1);
+        }
     }
 
     pub fn cluster_title<T: AsRef<str>>(message: T) {
-        Self::log_auto(Self::prefix_with_time(message), &["bold", "yellow", "on_magenta"], 2, 1);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(Self::prefix_with_time(message), &["bold", "yellow", "on_magenta"], 2, 1);
+        }
     }
 
     pub fn http_title<T: AsRef<str>>(message: T) {
@@ -66,7 +107,9 @@ impl Log {
     }
 
     pub fn discover_title<T: AsRef<str>>(message: T) {
-        Self::log_auto(Self::prefix_with_time(message), &["bold", "bright_cyan", "on_white"], 2, 1);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(Self::prefix_with_time(message), &["bold", "bright_cyan", "on_white"], 2, 1);
+        }
     }
 
     pub fn websocket_title<T: AsRef<str>>(message: T) {
@@ -74,7 +117,9 @@ impl Log {
     }
 
     pub fn webhook_sender_title<T: AsRef<str>>(message: T) {
-        Self::log_auto(Self::prefix_with_time(message), &["bold", "blue", "on_white"], 2, 1);
+        if Self::enabled(LogLevel::Info) {
+            Self::log_auto(Self::prefix_with_time(message), &["bold", "blue", "on_white"], 2, 1);
+        }
     }
 
     pub fn br() {
@@ -1,6 +1,8 @@
+use crate::config::ServerConfig;
 use crate::log::Log;
 use crate::server::start_server;
 
+pub mod app_manager;
 pub mod channel;
 pub mod connection;
 pub mod handlers;
@@ -8,12 +10,25 @@ pub mod protocol;
 pub mod error;
 pub mod server;
 pub mod application;
+pub mod auth;
+pub mod config;
 pub mod log;
+pub mod middleware;
+pub mod webhook;
 pub mod websocket;
 
 #[tokio::main]
 async fn main() {
-    match  start_server().await {
+    let config = match ServerConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            Log::error(format!("{}", e));
+            std::process::exit(1);
+        }
+    };
+    Log::set_level(config.log_level);
+
+    match start_server(config).await {
         Ok(_) => Log::info("Server started"),
         Err(e) => Log::error(format!("Error starting server: {}", e)),
     }
@@ -0,0 +1,172 @@
+use crate::auth::verify;
+use crate::error::AppError;
+use crate::log::Log;
+use crate::server::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use md5::{Digest, Md5};
+use std::collections::{BTreeMap, HashMap};
+
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+const MAX_TIMESTAMP_SKEW: i64 = 600;
+
+/// Verifies Pusher HTTP API request signing on every `/apps/:app_id/*` route.
+///
+/// The app is resolved by `auth_key`, `body_md5` is checked against the raw
+/// body, and `auth_signature` must be the HMAC-SHA256 of
+/// `METHOD\npath\nsorted_query` keyed with the app secret.
+pub async fn verify_api_signature(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_BODY_SIZE)
+        .await
+        .map_err(|e| AppError::BadRequest(format!("Failed to read request body: {}", e)))?;
+
+    let Query(query) = Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+        .map_err(|e| AppError::AuthenticationError(format!("Invalid query string: {}", e)))?;
+    let mut params_to_sign: BTreeMap<String, String> = query
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect();
+    let signature = params_to_sign
+        .remove("auth_signature")
+        .ok_or_else(|| AppError::AuthenticationError("Missing auth_signature".into()))?;
+
+    let auth_key = params_to_sign
+        .get("auth_key")
+        .ok_or_else(|| AppError::AuthenticationError("Missing auth_key".into()))?;
+    let app = state
+        .application_manager
+        .authenticate_key(auth_key)
+        .await?
+        .ok_or_else(|| AppError::AuthenticationError("Unknown auth_key".into()))?;
+    if params.get("app_id") != Some(&app.app_id) {
+        return Err(AppError::AuthenticationError(
+            "auth_key does not belong to this app".into(),
+        ));
+    }
+
+    let timestamp: i64 = params_to_sign
+        .get("auth_timestamp")
+        .and_then(|timestamp| timestamp.parse().ok())
+        .ok_or_else(|| AppError::AuthenticationError("Missing auth_timestamp".into()))?;
+    if (chrono::Utc::now().timestamp() - timestamp).abs() > MAX_TIMESTAMP_SKEW {
+        return Err(AppError::AuthenticationError(
+            "Timestamp expired: must be within 600 seconds of server time".into(),
+        ));
+    }
+
+    verify_body_md5(&params_to_sign, &body)?;
+
+    let string_to_sign = string_to_sign(&parts.method, parts.uri.path(), &params_to_sign);
+    if !verify(&app.secret, &string_to_sign, &signature) {
+        Log::warning(format!(
+            "Rejected API request to {}: invalid signature",
+            parts.uri.path()
+        ));
+        return Err(AppError::AuthenticationError("Invalid signature".into()));
+    }
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// A body, or a `body_md5` sent without one, must match its MD5 digest.
+fn verify_body_md5(params: &BTreeMap<String, String>, body: &[u8]) -> Result<(), AppError> {
+    let body_md5 = params.get("body_md5");
+    if !body.is_empty() || body_md5.is_some() {
+        let expected = hex::encode(Md5::digest(body));
+        if body_md5 != Some(&expected) {
+            return Err(AppError::AuthenticationError("Invalid body_md5".into()));
+        }
+    }
+    Ok(())
+}
+
+/// `METHOD\npath\nsorted_query`, with the lowercased query parameters other
+/// than `auth_signature` sorted by name.
+fn string_to_sign(method: &Method, path: &str, params: &BTreeMap<String, String>) -> String {
+    let query_string = params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}\n{}\n{}", method, path, query_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::sign;
+
+    // The worked example of https://pusher.com/docs/channels/library_auth_reference/rest-api/
+    const KEY: &str = "278d425bdf160c739803";
+    const SECRET: &str = "7ad3773142a6692b25b8";
+    const BODY: &str = r#"{"name":"foo","channels":["project-3"],"data":"{\"some\":\"data\"}"}"#;
+    const SIGNATURE: &str = "da454824c97ba181a32ccc17a72625ba02771f50b50e1e7430e47a1f3f457e6c";
+
+    fn example_params() -> BTreeMap<String, String> {
+        [
+            ("auth_version", "1.0"),
+            ("body_md5", "ec365a775a4cd0599faeb73354201b6f"),
+            ("auth_timestamp", "1353088179"),
+            ("auth_key", KEY),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn signs_pushers_example_request() {
+        let params = example_params();
+        assert!(verify_body_md5(&params, BODY.as_bytes()).is_ok());
+
+        let string_to_sign = string_to_sign(&Method::POST, "/apps/3/events", &params);
+        assert_eq!(
+            string_to_sign,
+            "POST\n/apps/3/events\nauth_key=278d425bdf160c739803&auth_timestamp=1353088179\
+             &auth_version=1.0&body_md5=ec365a775a4cd0599faeb73354201b6f"
+        );
+        assert_eq!(sign(SECRET, &string_to_sign), SIGNATURE);
+        assert!(verify(SECRET, &string_to_sign, SIGNATURE));
+        assert!(verify(SECRET, &string_to_sign, &SIGNATURE.to_uppercase()));
+    }
+
+    #[test]
+    fn refuses_other_requests_secrets_and_signatures() {
+        let params = example_params();
+        let string_to_sign = string_to_sign(&Method::POST, "/apps/3/events", &params);
+        assert!(!verify("another-secret", &string_to_sign, SIGNATURE));
+        assert!(!verify(
+            SECRET,
+            &string_to_sign.replace("/apps/3/", "/apps/4/"),
+            SIGNATURE
+        ));
+        assert!(!verify(SECRET, &string_to_sign, &SIGNATURE[..62]));
+        assert!(!verify(
+            SECRET,
+            &string_to_sign,
+            &SIGNATURE.replace('d', "g")
+        ));
+        assert!(!verify(SECRET, &string_to_sign, ""));
+    }
+
+    #[test]
+    fn body_md5_must_match_the_body() {
+        let params = example_params();
+        assert!(verify_body_md5(&params, br#"{"name":"bar"}"#).is_err());
+        assert!(verify_body_md5(&params, b"").is_err());
+
+        let mut unsigned = params.clone();
+        unsigned.remove("body_md5");
+        assert!(verify_body_md5(&unsigned, BODY.as_bytes()).is_err());
+        assert!(verify_body_md5(&unsigned, b"").is_ok());
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// How a client should react to a close code, decided by its range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconnectStrategy {
+    /// 4000-4099: reconnecting with the same parameters will not succeed.
+    DoNotReconnect,
+    /// 4100-4199: reconnect after backing off.
+    ReconnectWithBackoff,
+    /// 4200-4299: reconnect immediately.
+    ReconnectImmediately,
+}
+
+/// Close codes the server sends, following the Pusher protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum CloseCode {
+    /// Regular WebSocket close.
+    Normal = 1000,
+    /// No app uses the key the client connected with.
+    AppNotFound = 4001,
+    /// The app is disabled.
+    AppDisabled = 4003,
+    /// The app has reached its connection limit.
+    OverConnectionQuota = 4004,
+    /// The client speaks a protocol version this server does not support.
+    UnsupportedProtocolVersion = 4007,
+    /// The app terminated the connection, e.g. because its user was banned.
+    Unauthorized = 4009,
+    /// The server is over capacity.
+    OverCapacity = 4100,
+    /// Generic reconnect request, e.g. after an internal error.
+    GenericReconnect = 4200,
+    /// The client did not answer a `pusher:ping` in time.
+    PongNotReceived = 4201,
+}
+
+impl CloseCode {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    pub fn reconnect_strategy(self) -> ReconnectStrategy {
+        match self.code() {
+            4100..=4199 => ReconnectStrategy::ReconnectWithBackoff,
+            4200..=4299 => ReconnectStrategy::ReconnectImmediately,
+            _ => ReconnectStrategy::DoNotReconnect,
+        }
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({:?})", self.code(), self)
+    }
+}
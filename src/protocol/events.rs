@@ -29,12 +29,6 @@ pub enum PusherEvent {
         error: String,
     },
 
-    ClientEvent {
-        event: String,
-        channel: String,
-        data: Value,
-    },
-
     // This variant can be used for custom events
     Custom {
         channel: String,
@@ -47,9 +41,16 @@ pub enum PusherEvent {
 pub struct PusherApiEvent {
     pub name: String,
     pub(crate) data: String,
+    #[serde(default)]
     pub(crate) channels: Vec<String>,
     pub channel: Option<String>,
-    pub socket_id: Option<String>
+    pub socket_id: Option<String>,
+    pub info: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PusherBatchEvents {
+    pub batch: Vec<PusherApiEvent>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
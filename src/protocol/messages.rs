@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,6 +22,12 @@ pub enum PusherMessage {
     #[serde(rename = "pusher:unsubscribe")]
     Unsubscribe { channel: String },
 
+    #[serde(rename = "pusher:signin")]
+    Signin { auth: String, user_data: String },
+
+    #[serde(rename = "pusher:signin_success")]
+    SigninSuccess { user_data: String },
+
     #[serde(rename = "pusher:ping")]
     Ping { 
         #[serde(flatten)]
@@ -36,22 +42,59 @@ pub enum PusherMessage {
 
     #[serde(rename = "pusher:error")]
     Error { code: Option<u32>, message: String },
+}
 
-    #[serde(rename = "client_event")]
-    ClientEvent {
-        channel: String,
-        event: String,
-        data: Value,
-    },
+/// A `client-` prefixed event, relayed as-is to the other subscribers of a channel.
+///
+/// Client events carry `channel` next to `event` instead of inside `data`, so
+/// they do not fit the [`PusherMessage`] envelope.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientEventMessage {
+    pub event: String,
+    pub channel: String,
+    #[serde(default)]
+    pub data: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+impl ClientEventMessage {
+    pub fn is_client_event(message: &Value) -> bool {
+        message
+            .get("event")
+            .and_then(Value::as_str)
+            .is_some_and(|event| event.starts_with("client-"))
+    }
 }
     
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PresenceChannelData {
+    #[serde(deserialize_with = "deserialize_user_id")]
     pub user_id: String,
+    #[serde(default)]
     pub user_info: Value,
 }
 
+/// The `user_data` a client signs in with; only `id` is required.
+#[derive(Debug, Deserialize)]
+pub struct SigninUserData {
+    #[serde(deserialize_with = "deserialize_user_id")]
+    pub id: String,
+}
+
+/// Server libraries send `user_id` either as a string or as a number.
+fn deserialize_user_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(user_id) => Ok(user_id),
+        Value::Number(user_id) => Ok(user_id.to_string()),
+        _ => Err(serde::de::Error::custom("user_id must be a string or a number")),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChannelSubscription {
     pub channel: String,
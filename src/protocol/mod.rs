@@ -1,2 +1,3 @@
 pub mod messages;
-pub mod events;
\ No newline at end of file
+pub mod events;
+pub mod close_codes;
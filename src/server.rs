@@ -1,57 +1,97 @@
-use crate::application::{create_application_manager, SafeApplicationManager};
+use crate::application::{create_application_manager, Application, SafeApplicationManager};
+use crate::config::ServerConfig;
+use crate::connection::ClientInfo;
 use crate::error::AppError;
-use crate::handlers::http::events;
+use crate::handlers::http::{
+    batch_events, channels, connections, dead_letters, events, replay_dead_letters,
+    terminate_user_connections, user_events,
+};
 use crate::handlers::{
     http::{auth, channel_state, channel_users},
-    websocket::handle_socket,
+    websocket::{handle_socket, refuse_socket},
 };
-use crate::log::Log;
+use crate::log::{Log, LogLevel};
+use crate::middleware::verify_api_signature;
+use crate::protocol::close_codes::CloseCode;
+use crate::webhook::queue::{SafeWebhookQueue, WebhookQueue};
 use crate::websocket::WebSocketUpgrade;
 use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
 use axum::{
+    middleware,
     response::IntoResponse,
     routing::{get, post},
     Router,
 };
 use serde::Serialize;
-use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub application_manager: SafeApplicationManager,
+    pub webhook_queue: SafeWebhookQueue,
 }
 
-pub async fn run_server() -> Result<(), AppError> {
+pub async fn run_server(config: ServerConfig) -> Result<(), AppError> {
     // Initialize tracing
-    tracing_subscriber::fmt::init();
+    tracing_subscriber::fmt()
+        .with_max_level(match config.log_level {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Info => tracing::Level::INFO,
+        })
+        .init();
+
+    // Pending webhook deliveries survive restarts in this queue
+    let webhook_queue = WebhookQueue::start(&config.webhook_queue).await?;
 
     // Create application manager
-    let application_manager = create_application_manager();
+    let application_manager = create_application_manager(&config, webhook_queue.clone()).await?;
 
     // Create app state
     let app_state = AppState {
         application_manager,
+        webhook_queue,
     };
 
-    // Build our application with routes
-    let app = Router::new()
-        .route("/app/:app_id", get(ws_handler))
-        .route("/apps/:app_id/auth", post(auth))
+    // Pusher HTTP API routes, all of which must be signed with the app credentials
+    let api_routes = Router::new()
         .route(
             "/apps/:app_id/channels/:channel_name/users",
             get(channel_users),
         )
+        .route("/apps/:app_id/channels", get(channels))
         .route("/apps/:app_id/channels/:channel_name", get(channel_state))
         .route("/apps/:app_id/events", post(events))
+        .route("/apps/:app_id/batch_events", post(batch_events))
+        .route("/apps/:app_id/users/:user_id/events", post(user_events))
+        .route("/apps/:app_id/connections", get(connections))
+        .route(
+            "/apps/:app_id/users/:user_id/terminate_connections",
+            post(terminate_user_connections),
+        )
+        .route("/apps/:app_id/webhooks/dead_letters", get(dead_letters))
+        .route(
+            "/apps/:app_id/webhooks/dead_letters/replay",
+            post(replay_dead_letters),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            verify_api_signature,
+        ));
+
+    // Build our application with routes
+    let app = Router::new()
+        .route("/app/:key", get(ws_handler))
+        .route("/apps/:app_id/auth", post(auth))
+        .merge(api_routes)
         .with_state(app_state);
 
     // Run it
-    let addr = SocketAddr::from(([0, 0, 0, 0], 6001));
+    let addr = format!("{}:{}", config.host, config.port);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
     tracing::info!("listening on {}", addr);
-    Log::info("Server started on port 6001");
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:6001")
-        .await?;
+    Log::info(format!("Server started on {}", addr));
     match axum::serve(listener, app).await {
         Ok(_) => Ok(()),
         Err(e) => {
@@ -63,48 +103,212 @@ pub async fn run_server() -> Result<(), AppError> {
 
 #[derive(Debug, serde::Deserialize, Serialize)]
 struct PusherQuery {
-    protocol: String,
-    client: String,
-    version: String,
-    flash: String,
+    protocol: Option<String>,
+    client: Option<String>,
+    version: Option<String>,
+}
+
+/// Pusher protocol versions this server speaks.
+const SUPPORTED_PROTOCOL_VERSIONS: RangeInclusive<u8> = 5..=7;
+
+impl PusherQuery {
+    /// Returns what the client reported, or `None` when its protocol version is
+    /// missing or unsupported.
+    fn client_info(self) -> Option<ClientInfo> {
+        let protocol = self.protocol?.parse().ok()?;
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&protocol) {
+            return None;
+        }
+        Some(ClientInfo {
+            protocol,
+            client: self.client,
+            version: self.version,
+        })
+    }
+}
+
+/// Resolves the app and client of a handshake, or the close code and message
+/// the socket is refused with once upgraded.
+async fn accept_handshake(
+    state: &AppState,
+    key: &str,
+    pusher: PusherQuery,
+) -> Result<(Arc<Application>, ClientInfo), (CloseCode, String)> {
+    let protocol = pusher.protocol.clone();
+    let Some(client_info) = pusher.client_info() else {
+        Log::warning(format!("Unsupported protocol version: {:?}", protocol));
+        let message = format!(
+            "Unsupported protocol version {}, expected {} to {}",
+            protocol.as_deref().unwrap_or("(none)"),
+            SUPPORTED_PROTOCOL_VERSIONS.start(),
+            SUPPORTED_PROTOCOL_VERSIONS.end()
+        );
+        return Err((CloseCode::UnsupportedProtocolVersion, message));
+    };
+
+    match state.application_manager.authenticate_key(key).await {
+        Ok(Some(app)) => Ok((app, client_info)),
+        Ok(None) => {
+            Log::warning(format!("No app found for key: {}", key));
+            let message = format!("Could not find app by key {}", key);
+            Err((CloseCode::AppNotFound, message))
+        }
+        Err(e) => {
+            Log::error(format!("Failed to look up key {}: {}", key, e));
+            let message = "Failed to look up app".to_string();
+            Err((CloseCode::GenericReconnect, message))
+        }
+    }
 }
 
+/// Pusher clients connect to `/app/{key}`; keys that resolve to no app are refused
+/// after the upgrade so the client sees a 4001 close instead of a failed handshake.
 async fn ws_handler(
-    Path(app_id): Path<String>,
+    Path(key): Path<String>,
     State(state): State<AppState>,
     Query(pusher): Query<PusherQuery>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    Log::info(format!(
-        "New WebSocket connection request for app: {}",
-        app_id
-    ));
+    Log::info(format!("New WebSocket connection request for key: {}", key));
     Log::success(format!("Pusher query: {:?}", pusher));
 
-    match state.application_manager.get_application(&app_id).await {
-        Some(app) => {
-            let channel_manager = app.channel_manager.clone();
-            let connection_manager = app.connection_manager.clone();
-
-            ws.on_upgrade(move |socket| async move {
-                handle_socket(
-                    socket,
-                    &channel_manager,
-                    &connection_manager, // Clone the query params to use in handle_socket if needed
-                )
-                .await;
-            })
-        }
-        None => {
-            Log::error(format!("Application not found: {}", app_id));
-            (StatusCode::NOT_FOUND, "Application not found").into_response()
-        }
+    match accept_handshake(&state, &key, pusher).await {
+        Ok((app, client_info)) => ws.on_upgrade(move |socket| async move {
+            handle_socket(socket, app, client_info).await;
+        }),
+        Err((code, message)) => ws.on_upgrade(move |socket| async move {
+            refuse_socket(socket, code, message).await;
+        }),
     }
 }
 
-pub async fn start_server() -> Result<(), AppError> {
+pub async fn start_server(config: ServerConfig) -> Result<(), AppError> {
     // You might want to perform any necessary setup here
     // For example, loading applications from a database
 
-    run_server().await
+    run_server(config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_manager::static_app_manager::StaticAppManager;
+    use crate::application::ApplicationManager;
+    use crate::config::{AppConfig, AppLimits, AppTimeouts, TimeoutConfig, WebhookQueueConfig};
+    use axum::http::Uri;
+    use std::path::PathBuf;
+
+    /// State serving the single app `1` with the key `key`.
+    async fn state() -> AppState {
+        let app = AppConfig {
+            id: "1".to_string(),
+            key: "key".to_string(),
+            secret: "secret".to_string(),
+            enabled: true,
+            enable_client_messages: true,
+            limits: AppLimits::default(),
+            timeouts: AppTimeouts::default(),
+            webhooks: Vec::new(),
+            authorizer: None,
+            open_auth: false,
+            channel_rules: None,
+            jwt: None,
+        };
+        let webhook_queue = WebhookQueue::start(&WebhookQueueConfig {
+            path: PathBuf::from(":memory:"),
+            ..WebhookQueueConfig::default()
+        })
+        .await
+        .unwrap();
+        let application_manager = ApplicationManager::new(
+            Arc::new(StaticAppManager::new(vec![app])),
+            TimeoutConfig::default(),
+            webhook_queue.clone(),
+        );
+        AppState {
+            application_manager: Arc::new(application_manager),
+            webhook_queue,
+        }
+    }
+
+    fn query(query: &str) -> PusherQuery {
+        let uri: Uri = format!("/app/key?{}", query).parse().unwrap();
+        Query::<PusherQuery>::try_from_uri(&uri).unwrap().0
+    }
+
+    async fn close_code(state: &AppState, key: &str, pusher: PusherQuery) -> Option<u16> {
+        accept_handshake(state, key, pusher)
+            .await
+            .err()
+            .map(|(code, _)| code.code())
+    }
+
+    #[test]
+    fn client_and_version_are_optional() {
+        let client_info = query("protocol=7").client_info().unwrap();
+        assert_eq!(client_info.protocol, 7);
+        assert!(client_info.client.is_none() && client_info.version.is_none());
+
+        // Old clients still send flash, which is ignored
+        let client_info = query("protocol=5&client=js&version=8.4.0&flash=false")
+            .client_info()
+            .unwrap();
+        assert_eq!(client_info.protocol, 5);
+        assert_eq!(client_info.client.as_deref(), Some("js"));
+        assert_eq!(client_info.version.as_deref(), Some("8.4.0"));
+    }
+
+    #[test]
+    fn only_protocols_5_to_7_are_supported() {
+        for protocol in ["5", "6", "7"] {
+            let pusher = query(&format!("protocol={}", protocol));
+            assert!(pusher.client_info().is_some(), "protocol {}", protocol);
+        }
+        for unsupported in [
+            "",
+            "protocol=",
+            "protocol=seven",
+            "protocol=4",
+            "protocol=8",
+        ] {
+            assert!(
+                query(unsupported).client_info().is_none(),
+                "{}",
+                unsupported
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_keys_are_refused_with_4001() {
+        let state = state().await;
+        let (app, client_info) = accept_handshake(&state, "key", query("protocol=7&client=js"))
+            .await
+            .unwrap();
+        assert_eq!(app.app_id, "1");
+        assert_eq!(client_info.client.as_deref(), Some("js"));
+
+        assert_eq!(
+            close_code(&state, "unknown", query("protocol=7")).await,
+            Some(4001)
+        );
+        // The app id is not a key
+        assert_eq!(
+            close_code(&state, "1", query("protocol=7")).await,
+            Some(4001)
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_protocols_are_refused_with_4007() {
+        let state = state().await;
+        for unsupported in ["", "protocol=seven", "protocol=4", "protocol=8"] {
+            assert_eq!(
+                close_code(&state, "key", query(unsupported)).await,
+                Some(4007),
+                "{}",
+                unsupported
+            );
+        }
+    }
 }
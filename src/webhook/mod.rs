@@ -0,0 +1,121 @@
+pub mod queue;
+pub mod sender;
+pub mod store;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Webhook queue error: {0}")]
+    Database(String),
+}
+
+impl From<rusqlite::Error> for WebhookError {
+    fn from(err: rusqlite::Error) -> Self {
+        WebhookError::Database(err.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for WebhookError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        WebhookError::Database(err.to_string())
+    }
+}
+
+/// Pusher webhook event names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    ChannelOccupied,
+    ChannelVacated,
+    MemberAdded,
+    MemberRemoved,
+    ClientEvent,
+}
+
+/// A webhook endpoint of an app.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event types delivered to this endpoint; all of them when empty.
+    #[serde(default)]
+    pub event_types: Vec<WebhookEventType>,
+}
+
+impl WebhookConfig {
+    pub fn accepts(&self, event_type: WebhookEventType) -> bool {
+        self.event_types.is_empty() || self.event_types.contains(&event_type)
+    }
+}
+
+/// One entry of the `events` array of a webhook request.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    pub name: WebhookEventType,
+    pub channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    /// The client event's payload as a JSON string, like Pusher sends it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+impl WebhookEvent {
+    fn new(name: WebhookEventType, channel: &str) -> Self {
+        Self {
+            name,
+            channel: channel.to_string(),
+            event: None,
+            data: None,
+            socket_id: None,
+            user_id: None,
+        }
+    }
+
+    pub fn channel_occupied(channel: &str) -> Self {
+        Self::new(WebhookEventType::ChannelOccupied, channel)
+    }
+
+    pub fn channel_vacated(channel: &str) -> Self {
+        Self::new(WebhookEventType::ChannelVacated, channel)
+    }
+
+    pub fn member_added(channel: &str, user_id: &str) -> Self {
+        Self {
+            user_id: Some(user_id.to_string()),
+            ..Self::new(WebhookEventType::MemberAdded, channel)
+        }
+    }
+
+    pub fn member_removed(channel: &str, user_id: &str) -> Self {
+        Self {
+            user_id: Some(user_id.to_string()),
+            ..Self::new(WebhookEventType::MemberRemoved, channel)
+        }
+    }
+
+    pub fn client_event(
+        channel: &str,
+        event: &str,
+        data: Value,
+        socket_id: &str,
+        user_id: Option<String>,
+    ) -> Self {
+        Self {
+            event: Some(event.to_string()),
+            data: Some(match data {
+                Value::String(data) => data,
+                data => data.to_string(),
+            }),
+            socket_id: Some(socket_id.to_string()),
+            user_id,
+            ..Self::new(WebhookEventType::ClientEvent, channel)
+        }
+    }
+}
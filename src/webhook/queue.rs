@@ -0,0 +1,346 @@
+use super::store::{DeadLetter, NewWebhookJob, WebhookJob, WebhookStore};
+use super::WebhookError;
+use crate::config::WebhookQueueConfig;
+use crate::log::Log;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+/// Jobs read from the store per dispatch round.
+const DISPATCH_BATCH_SIZE: usize = 100;
+/// Retries and circuits that come due are picked up at least this often.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Failure tracking of one webhook URL.
+///
+/// After `threshold` consecutive failures the circuit opens and no deliveries are
+/// made to the URL for the cooldown. A single trial delivery then decides whether
+/// it closes again or stays open for another cooldown.
+#[derive(Default)]
+struct CircuitBreaker {
+    failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    fn is_blocked(&self, threshold: u32, now: Instant) -> bool {
+        self.failures >= threshold
+            && (self.probing || self.open_until.is_some_and(|until| until > now))
+    }
+
+    /// Returns whether a delivery may go out, marking it as the trial when the
+    /// circuit is half open.
+    fn allow(&mut self, threshold: u32, now: Instant) -> bool {
+        if self.is_blocked(threshold, now) {
+            return false;
+        }
+        if self.failures >= threshold {
+            self.probing = true;
+        }
+        true
+    }
+
+    fn record_success(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns `true` when this failure opened the circuit.
+    fn record_failure(&mut self, threshold: u32, cooldown: Duration) -> bool {
+        let now = Instant::now();
+        let was_open = self.open_until.is_some_and(|until| until > now);
+        self.failures += 1;
+        self.probing = false;
+        if self.failures >= threshold {
+            self.open_until = Some(now + cooldown);
+            return !was_open;
+        }
+        false
+    }
+}
+
+/// Persistent webhook deliveries for all apps.
+///
+/// Jobs are written to the [`WebhookStore`] before anything is sent and are
+/// delivered by a single dispatcher, which limits how many deliveries each app
+/// has in flight and skips URLs whose circuit is open. Jobs that fail
+/// `max_attempts` times are moved to the dead-letter table, from where they can
+/// be replayed.
+pub struct WebhookQueue {
+    store: WebhookStore,
+    config: WebhookQueueConfig,
+    client: reqwest::Client,
+    wake: Notify,
+    permits: Mutex<HashMap<String, Arc<Semaphore>>>,
+    breakers: Mutex<HashMap<String, CircuitBreaker>>,
+}
+
+pub type SafeWebhookQueue = Arc<WebhookQueue>;
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+impl WebhookQueue {
+    /// Opens the store at `config.path` and starts the dispatcher.
+    pub async fn start(config: &WebhookQueueConfig) -> Result<SafeWebhookQueue, WebhookError> {
+        let store = WebhookStore::open(config.path.clone()).await?;
+        let queue = Arc::new(Self {
+            store,
+            config: config.clone(),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            wake: Notify::new(),
+            permits: Mutex::new(HashMap::new()),
+            breakers: Mutex::new(HashMap::new()),
+        });
+        tokio::spawn(queue.clone().dispatch());
+        Ok(queue)
+    }
+
+    pub async fn enqueue(&self, jobs: Vec<NewWebhookJob>) -> Result<(), WebhookError> {
+        self.store.enqueue(jobs, now_ms()).await?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    pub async fn dead_letters(
+        &self,
+        app_id: &str,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, WebhookError> {
+        self.store.dead_letters(app_id, limit).await
+    }
+
+    /// Queues dead letters of an app for delivery again, re-signed with `sign`.
+    pub async fn replay<F>(
+        &self,
+        app_id: &str,
+        ids: Option<Vec<i64>>,
+        app_key: String,
+        sign: F,
+    ) -> Result<usize, WebhookError>
+    where
+        F: Fn(&str) -> String + Send + 'static,
+    {
+        let replayed = self
+            .store
+            .replay(app_id, ids, app_key, sign, now_ms())
+            .await?;
+        self.wake.notify_one();
+        Ok(replayed)
+    }
+
+    async fn dispatch(self: Arc<Self>) {
+        loop {
+            if let Err(e) = self.dispatch_due().await {
+                Log::error(format!("Failed to dispatch webhooks: {}", e));
+            }
+            let _ = tokio::time::timeout(POLL_INTERVAL, self.wake.notified()).await;
+        }
+    }
+
+    /// Starts every due job its app and endpoint currently have room for.
+    async fn dispatch_due(self: &Arc<Self>) -> Result<(), WebhookError> {
+        let now = Instant::now();
+        let busy_apps: Vec<String> = self
+            .permits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(_, permits)| permits.available_permits() == 0)
+            .map(|(app_id, _)| app_id.clone())
+            .collect();
+        let blocked_urls: Vec<String> = self
+            .breakers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(_, breaker)| breaker.is_blocked(self.config.breaker_threshold, now))
+            .map(|(url, _)| url.clone())
+            .collect();
+
+        let jobs = self
+            .store
+            .due(now_ms(), DISPATCH_BATCH_SIZE, busy_apps, blocked_urls)
+            .await?;
+        for job in jobs {
+            let Some(permit) = self.try_reserve(&job) else {
+                continue;
+            };
+            if let Err(e) = self.store.claim(job.id).await {
+                // Let the next round try the endpoint again
+                self.breaker(&job.url, |breaker| breaker.probing = false);
+                return Err(e);
+            }
+            let queue = self.clone();
+            tokio::spawn(async move {
+                queue.deliver(job).await;
+                drop(permit);
+                queue.wake.notify_one();
+            });
+        }
+        Ok(())
+    }
+
+    /// Takes one of the app's delivery slots if the endpoint's circuit lets the job through.
+    fn try_reserve(&self, job: &WebhookJob) -> Option<OwnedSemaphorePermit> {
+        let permits = self
+            .permits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(job.app_id.clone())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.config.concurrency)))
+            .clone();
+        let permit = permits.try_acquire_owned().ok()?;
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let breaker = breakers.entry(job.url.clone()).or_default();
+        breaker
+            .allow(self.config.breaker_threshold, Instant::now())
+            .then_some(permit)
+    }
+
+    async fn deliver(&self, job: WebhookJob) {
+        let result = self
+            .client
+            .post(&job.url)
+            .header("Content-Type", "application/json")
+            .header("X-Pusher-Key", &job.app_key)
+            .header("X-Pusher-Signature", &job.signature)
+            .body(job.body.clone())
+            .send()
+            .await;
+        let failure = match result {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) => Some(format!("responded with {}", response.status())),
+            Err(e) => Some(e.to_string()),
+        };
+
+        let stored = match failure {
+            None => {
+                self.breaker(&job.url, |breaker| breaker.record_success());
+                Log::webhook_sender(format!(
+                    "Delivered webhook of app {} to {}",
+                    job.app_id, job.url
+                ));
+                self.store.complete(job.id).await
+            }
+            Some(failure) => self.record_failure(&job, failure).await,
+        };
+        if let Err(e) = stored {
+            Log::error(format!("Failed to update webhook job {}: {}", job.id, e));
+        }
+    }
+
+    async fn record_failure(&self, job: &WebhookJob, failure: String) -> Result<(), WebhookError> {
+        let WebhookQueueConfig {
+            max_attempts,
+            retry_delay,
+            breaker_threshold,
+            breaker_cooldown,
+            ..
+        } = self.config;
+        let attempt = job.attempts + 1;
+        Log::warning(format!(
+            "Webhook of app {} to {} failed (attempt {}/{}): {}",
+            job.app_id, job.url, attempt, max_attempts, failure
+        ));
+        let opened = self.breaker(&job.url, |breaker| {
+            breaker.record_failure(breaker_threshold, Duration::from_secs(breaker_cooldown))
+        });
+        if opened {
+            Log::warning(format!(
+                "Pausing webhooks to {} for {}s after {} consecutive failures",
+                job.url, breaker_cooldown, breaker_threshold
+            ));
+        }
+        if attempt >= max_attempts {
+            Log::error(format!(
+                "Dead-lettered webhook of app {} to {} after {} attempts",
+                job.app_id, job.url, attempt
+            ));
+            return self.store.dead_letter(job.id, failure, now_ms()).await;
+        }
+        let retry_at = now_ms().saturating_add(backoff_ms(retry_delay, attempt));
+        self.store.retry_later(job.id, failure, retry_at).await
+    }
+
+    fn breaker<T>(&self, url: &str, f: impl FnOnce(&mut CircuitBreaker) -> T) -> T {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        f(breakers.entry(url.to_string()).or_default())
+    }
+}
+
+/// Milliseconds to wait before retrying after failed attempt `attempt`: `retry_delay`
+/// seconds, doubled for every attempt after the first.
+fn backoff_ms(retry_delay: u64, attempt: u32) -> i64 {
+    let delay = retry_delay
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .saturating_mul(1000);
+    i64::try_from(delay).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: u32 = 3;
+    const COOLDOWN: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn backoff_doubles_and_saturates() {
+        assert_eq!(backoff_ms(1, 1), 1_000);
+        assert_eq!(backoff_ms(1, 3), 4_000);
+        assert_eq!(backoff_ms(1, 40), 65_536_000);
+        assert_eq!(backoff_ms(u64::MAX / 1000, 2), i64::MAX);
+        assert_eq!(backoff_ms(u64::MAX, 1), i64::MAX);
+    }
+
+    #[test]
+    fn circuit_opens_after_threshold_consecutive_failures() {
+        let mut breaker = CircuitBreaker::default();
+        let now = Instant::now();
+        for _ in 1..THRESHOLD {
+            assert!(breaker.allow(THRESHOLD, now));
+            assert!(!breaker.record_failure(THRESHOLD, COOLDOWN));
+        }
+        breaker.record_success();
+        for _ in 1..THRESHOLD {
+            assert!(!breaker.record_failure(THRESHOLD, COOLDOWN));
+        }
+        assert!(breaker.allow(THRESHOLD, now));
+        assert!(breaker.record_failure(THRESHOLD, COOLDOWN));
+        assert!(!breaker.allow(THRESHOLD, Instant::now()));
+        assert!(breaker.is_blocked(THRESHOLD, Instant::now()));
+    }
+
+    #[test]
+    fn one_trial_goes_out_after_the_cooldown() {
+        let mut breaker = CircuitBreaker::default();
+        for _ in 0..THRESHOLD {
+            breaker.record_failure(THRESHOLD, COOLDOWN);
+        }
+        let later = Instant::now() + COOLDOWN + Duration::from_secs(1);
+        assert!(breaker.allow(THRESHOLD, later));
+        assert!(!breaker.allow(THRESHOLD, later));
+
+        // A failed trial keeps the circuit open for another cooldown
+        breaker.record_failure(THRESHOLD, COOLDOWN);
+        assert!(!breaker.allow(THRESHOLD, Instant::now()));
+        let later = Instant::now() + COOLDOWN + Duration::from_secs(1);
+        assert!(breaker.allow(THRESHOLD, later));
+
+        // and a successful one closes it
+        breaker.record_success();
+        assert!(breaker.allow(THRESHOLD, Instant::now()));
+        assert!(breaker.allow(THRESHOLD, Instant::now()));
+        assert!(!breaker.record_failure(THRESHOLD, COOLDOWN));
+    }
+}
@@ -0,0 +1,284 @@
+use super::queue::SafeWebhookQueue;
+use super::store::NewWebhookJob;
+use super::{WebhookConfig, WebhookEvent};
+use crate::auth::sign;
+use crate::log::Log;
+use serde_json::json;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Events waiting to be batched; further events are dropped while it is full.
+const EVENT_QUEUE_SIZE: usize = 10_000;
+/// How long the first event of a batch waits for more events.
+const BATCH_WINDOW: Duration = Duration::from_millis(100);
+const MAX_BATCH_SIZE: usize = 50;
+
+struct Endpoints {
+    app_id: String,
+    app_key: String,
+    app_secret: String,
+    webhooks: Vec<WebhookConfig>,
+}
+
+/// Batches the webhook events of an app and hands one signed job per webhook URL
+/// to the [`WebhookQueue`](super::queue::WebhookQueue).
+#[derive(Clone)]
+pub struct WebhookSender {
+    events: mpsc::Sender<WebhookEvent>,
+}
+
+impl WebhookSender {
+    /// Spawns the batching task, which stops once every sender has been dropped.
+    pub fn new(
+        app_id: String,
+        app_key: String,
+        app_secret: String,
+        webhooks: Vec<WebhookConfig>,
+        queue: SafeWebhookQueue,
+    ) -> Self {
+        let (events, receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+        let endpoints = Endpoints {
+            app_id,
+            app_key,
+            app_secret,
+            webhooks,
+        };
+        tokio::spawn(run_batches(receiver, endpoints, queue));
+        Self { events }
+    }
+
+    pub fn send(&self, event: WebhookEvent) {
+        if let Err(e) = self.events.try_send(event) {
+            Log::warning(format!("Dropped webhook event: {}", e));
+        }
+    }
+}
+
+async fn run_batches(
+    mut receiver: mpsc::Receiver<WebhookEvent>,
+    endpoints: Endpoints,
+    queue: SafeWebhookQueue,
+) {
+    while let Some(batch) = next_batch(&mut receiver).await {
+        let jobs = jobs(&endpoints, &batch);
+        if jobs.is_empty() {
+            continue;
+        }
+        if let Err(e) = queue.enqueue(jobs).await {
+            Log::error(format!(
+                "Lost {} webhook events of app {}: {}",
+                batch.len(),
+                endpoints.app_id,
+                e
+            ));
+        }
+    }
+}
+
+/// Waits for an event and collects whatever else arrives within [`BATCH_WINDOW`],
+/// up to [`MAX_BATCH_SIZE`] events. Returns `None` once every sender is gone.
+async fn next_batch(receiver: &mut mpsc::Receiver<WebhookEvent>) -> Option<Vec<WebhookEvent>> {
+    let mut batch = vec![receiver.recv().await?];
+    let deadline = Instant::now() + BATCH_WINDOW;
+    while batch.len() < MAX_BATCH_SIZE {
+        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+            Ok(Some(event)) => batch.push(event),
+            _ => break,
+        }
+    }
+    Some(batch)
+}
+
+/// Builds one job per webhook URL that accepts any event of the batch.
+fn jobs(endpoints: &Endpoints, batch: &[WebhookEvent]) -> Vec<NewWebhookJob> {
+    let time_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    endpoints
+        .webhooks
+        .iter()
+        .filter_map(|webhook| {
+            let events: Vec<&WebhookEvent> = batch
+                .iter()
+                .filter(|event| webhook.accepts(event.name))
+                .collect();
+            if events.is_empty() {
+                return None;
+            }
+            let body = json!({ "time_ms": time_ms, "events": events }).to_string();
+            Some(NewWebhookJob {
+                app_id: endpoints.app_id.clone(),
+                url: webhook.url.clone(),
+                app_key: endpoints.app_key.clone(),
+                signature: sign(&endpoints.app_secret, &body),
+                body,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::verify;
+    use crate::webhook::WebhookEventType;
+    use serde_json::Value;
+
+    fn endpoints(webhooks: Vec<WebhookConfig>) -> Endpoints {
+        Endpoints {
+            app_id: "1".to_string(),
+            app_key: "key".to_string(),
+            app_secret: "secret".to_string(),
+            webhooks,
+        }
+    }
+
+    fn webhook(url: &str, event_types: Vec<WebhookEventType>) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_string(),
+            event_types,
+        }
+    }
+
+    fn event_names(body: &str) -> Vec<String> {
+        let body: Value = serde_json::from_str(body).unwrap();
+        body["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn jobs_are_signed_over_the_exact_body() {
+        let endpoints = endpoints(vec![webhook("https://example.com/hooks", vec![])]);
+        let batch = [
+            WebhookEvent::channel_occupied("presence-room"),
+            WebhookEvent::member_added("presence-room", "7"),
+        ];
+        let jobs = jobs(&endpoints, &batch);
+        assert_eq!(jobs.len(), 1);
+        let job = &jobs[0];
+        assert_eq!(
+            (job.app_id.as_str(), job.url.as_str(), job.app_key.as_str()),
+            ("1", "https://example.com/hooks", "key")
+        );
+        assert_eq!(job.signature, sign("secret", &job.body));
+        assert!(verify("secret", &job.body, &job.signature));
+
+        let body: Value = serde_json::from_str(&job.body).unwrap();
+        let object = body.as_object().unwrap();
+        assert_eq!(object.len(), 2);
+        assert!(body["time_ms"].as_u64().unwrap() > 0);
+        assert_eq!(
+            body["events"],
+            serde_json::json!([
+                { "name": "channel_occupied", "channel": "presence-room" },
+                { "name": "member_added", "channel": "presence-room", "user_id": "7" },
+            ])
+        );
+    }
+
+    #[test]
+    fn urls_only_get_the_event_types_they_filter_for() {
+        let endpoints = endpoints(vec![
+            webhook("https://example.com/all", vec![]),
+            webhook(
+                "https://example.com/presence",
+                vec![
+                    WebhookEventType::MemberAdded,
+                    WebhookEventType::MemberRemoved,
+                ],
+            ),
+            webhook(
+                "https://example.com/client",
+                vec![WebhookEventType::ClientEvent],
+            ),
+        ]);
+        let batch = [
+            WebhookEvent::channel_occupied("presence-room"),
+            WebhookEvent::member_added("presence-room", "7"),
+        ];
+        let jobs = jobs(&endpoints, &batch);
+        let urls: Vec<&str> = jobs.iter().map(|job| job.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec!["https://example.com/all", "https://example.com/presence"]
+        );
+        assert_eq!(
+            event_names(&jobs[0].body),
+            vec!["channel_occupied", "member_added"]
+        );
+        assert_eq!(event_names(&jobs[1].body), vec!["member_added"]);
+    }
+
+    #[test]
+    fn client_event_data_is_a_string() {
+        let endpoints = endpoints(vec![webhook("https://example.com/hooks", vec![])]);
+        let batch = [WebhookEvent::client_event(
+            "private-chat",
+            "client-typing",
+            serde_json::json!({ "typing": true }),
+            "1.1",
+            None,
+        )];
+        let body: Value = serde_json::from_str(&jobs(&endpoints, &batch)[0].body).unwrap();
+        assert_eq!(body["events"][0]["data"], r#"{"typing":true}"#);
+        assert_eq!(body["events"][0]["event"], "client-typing");
+        assert_eq!(body["events"][0]["socket_id"], "1.1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn events_within_the_window_share_a_batch() {
+        let (events, mut receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+        events
+            .send(WebhookEvent::channel_occupied("a"))
+            .await
+            .unwrap();
+        let later = events.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(BATCH_WINDOW / 2).await;
+            later
+                .send(WebhookEvent::channel_occupied("b"))
+                .await
+                .unwrap();
+            tokio::time::sleep(BATCH_WINDOW).await;
+            later
+                .send(WebhookEvent::channel_occupied("c"))
+                .await
+                .unwrap();
+        });
+
+        let started = Instant::now();
+        let batch = next_batch(&mut receiver).await.unwrap();
+        let channels: Vec<&str> = batch.iter().map(|event| event.channel.as_str()).collect();
+        assert_eq!(channels, vec!["a", "b"]);
+        assert_eq!(started.elapsed(), BATCH_WINDOW);
+
+        let batch = next_batch(&mut receiver).await.unwrap();
+        assert_eq!(batch[0].channel, "c");
+        drop(events);
+        assert!(next_batch(&mut receiver).await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_batches_go_out_without_waiting() {
+        let (events, mut receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+        for i in 0..MAX_BATCH_SIZE + 1 {
+            events
+                .send(WebhookEvent::channel_occupied(&i.to_string()))
+                .await
+                .unwrap();
+        }
+        let started = Instant::now();
+        assert_eq!(
+            next_batch(&mut receiver).await.unwrap().len(),
+            MAX_BATCH_SIZE
+        );
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        assert_eq!(next_batch(&mut receiver).await.unwrap().len(), 1);
+    }
+}
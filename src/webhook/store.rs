@@ -0,0 +1,441 @@
+use super::WebhookError;
+use rusqlite::{params, params_from_iter, Connection, Row};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS webhook_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    app_key TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    body TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    in_flight INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+CREATE INDEX IF NOT EXISTS webhook_jobs_due ON webhook_jobs (in_flight, next_attempt_at);
+CREATE TABLE IF NOT EXISTS webhook_dead_letters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    app_key TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    body TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT NOT NULL,
+    failed_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS webhook_dead_letters_app ON webhook_dead_letters (app_id);";
+
+const JOB_COLUMNS: &str = "id, app_id, url, app_key, signature, body, attempts";
+
+/// A signed request body waiting to be posted to one webhook URL.
+#[derive(Debug, Clone)]
+pub struct NewWebhookJob {
+    pub app_id: String,
+    pub url: String,
+    pub app_key: String,
+    pub signature: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookJob {
+    pub id: i64,
+    pub app_id: String,
+    pub url: String,
+    pub app_key: String,
+    pub signature: String,
+    pub body: String,
+    /// Attempts made before this one.
+    pub attempts: u32,
+}
+
+/// A delivery that used up its attempts.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub url: String,
+    pub body: String,
+    pub attempts: u32,
+    pub last_error: String,
+    /// Unix time in milliseconds.
+    pub failed_at: i64,
+}
+
+/// Pending and dead-lettered webhook deliveries in a SQLite database.
+///
+/// Claimed jobs are flagged `in_flight` and only removed once delivered or
+/// dead-lettered, so a restart retries whatever was interrupted.
+#[derive(Clone)]
+pub struct WebhookStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl WebhookStore {
+    /// Opens the database, creating its tables and releasing jobs that were in
+    /// flight when the server last stopped.
+    pub async fn open(path: PathBuf) -> Result<Self, WebhookError> {
+        let connection = tokio::task::spawn_blocking(move || {
+            let connection = Connection::open(&path)?;
+            connection.execute_batch(SCHEMA)?;
+            connection.execute(
+                "UPDATE webhook_jobs SET in_flight = 0 WHERE in_flight = 1",
+                [],
+            )?;
+            Ok::<_, rusqlite::Error>(connection)
+        })
+        .await??;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, WebhookError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut connection)
+        })
+        .await??;
+        Ok(result)
+    }
+
+    pub async fn enqueue(&self, jobs: Vec<NewWebhookJob>, now: i64) -> Result<(), WebhookError> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            for job in &jobs {
+                transaction.execute(
+                    "INSERT INTO webhook_jobs (app_id, url, app_key, signature, body, next_attempt_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![job.app_id, job.url, job.app_key, job.signature, job.body, now],
+                )?;
+            }
+            transaction.commit()
+        })
+        .await
+    }
+
+    /// Returns up to `limit` due jobs, oldest first, skipping the given apps and URLs.
+    /// The jobs are not claimed yet; see [`claim`](Self::claim).
+    pub async fn due(
+        &self,
+        now: i64,
+        limit: usize,
+        skip_apps: Vec<String>,
+        skip_urls: Vec<String>,
+    ) -> Result<Vec<WebhookJob>, WebhookError> {
+        self.with_connection(move |connection| {
+            let placeholders = |count: usize, offset: usize| {
+                (1..=count)
+                    .map(|index| format!("?{}", index + offset))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let sql = format!(
+                "SELECT {} FROM webhook_jobs WHERE in_flight = 0 AND next_attempt_at <= {} \
+                 AND app_id NOT IN ({}) AND url NOT IN ({}) ORDER BY next_attempt_at, id LIMIT {}",
+                JOB_COLUMNS,
+                now,
+                placeholders(skip_apps.len(), 0),
+                placeholders(skip_urls.len(), skip_apps.len()),
+                limit
+            );
+            let values = skip_apps.into_iter().chain(skip_urls);
+            let mut statement = connection.prepare(&sql)?;
+            let jobs = statement
+                .query_map(params_from_iter(values), job_from_row)?
+                .collect();
+            jobs
+        })
+        .await
+    }
+
+    pub async fn claim(&self, id: i64) -> Result<(), WebhookError> {
+        self.with_connection(move |connection| {
+            connection.execute("UPDATE webhook_jobs SET in_flight = 1 WHERE id = ?1", [id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn complete(&self, id: i64) -> Result<(), WebhookError> {
+        self.with_connection(move |connection| {
+            connection.execute("DELETE FROM webhook_jobs WHERE id = ?1", [id])?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Records a failed attempt and releases the job until `next_attempt_at`.
+    pub async fn retry_later(
+        &self,
+        id: i64,
+        error: String,
+        next_attempt_at: i64,
+    ) -> Result<(), WebhookError> {
+        self.with_connection(move |connection| {
+            connection.execute(
+                "UPDATE webhook_jobs SET attempts = attempts + 1, last_error = ?2, \
+                 next_attempt_at = ?3, in_flight = 0 WHERE id = ?1",
+                params![id, error, next_attempt_at],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Moves a job whose last attempt failed to the dead-letter table.
+    pub async fn dead_letter(&self, id: i64, error: String, now: i64) -> Result<(), WebhookError> {
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "INSERT INTO webhook_dead_letters \
+                 (app_id, url, app_key, signature, body, attempts, last_error, failed_at) \
+                 SELECT app_id, url, app_key, signature, body, attempts + 1, ?2, ?3 \
+                 FROM webhook_jobs WHERE id = ?1",
+                params![id, error, now],
+            )?;
+            transaction.execute("DELETE FROM webhook_jobs WHERE id = ?1", [id])?;
+            transaction.commit()
+        })
+        .await
+    }
+
+    pub async fn dead_letters(
+        &self,
+        app_id: &str,
+        limit: usize,
+    ) -> Result<Vec<DeadLetter>, WebhookError> {
+        let app_id = app_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT id, url, body, attempts, last_error, failed_at FROM webhook_dead_letters \
+                 WHERE app_id = ?1 ORDER BY id LIMIT ?2",
+            )?;
+            let dead_letters = statement
+                .query_map(params![app_id, limit as i64], |row| {
+                    Ok(DeadLetter {
+                        id: row.get(0)?,
+                        url: row.get(1)?,
+                        body: row.get(2)?,
+                        attempts: row.get(3)?,
+                        last_error: row.get(4)?,
+                        failed_at: row.get(5)?,
+                    })
+                })?
+                .collect();
+            dead_letters
+        })
+        .await
+    }
+
+    /// Queues dead letters of `app_id` again, all of them when `ids` is `None`,
+    /// re-signing each body with `sign`. Returns how many were queued.
+    pub async fn replay<F>(
+        &self,
+        app_id: &str,
+        ids: Option<Vec<i64>>,
+        app_key: String,
+        sign: F,
+        now: i64,
+    ) -> Result<usize, WebhookError>
+    where
+        F: Fn(&str) -> String + Send + 'static,
+    {
+        let app_id = app_id.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let replayable: Vec<(i64, String, String)> = {
+                let mut statement = transaction.prepare(
+                    "SELECT id, url, body FROM webhook_dead_letters WHERE app_id = ?1 ORDER BY id",
+                )?;
+                let rows = statement
+                    .query_map([&app_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                rows.into_iter()
+                    .filter(|(id, _, _)| ids.as_ref().is_none_or(|ids| ids.contains(id)))
+                    .collect()
+            };
+            for (id, url, body) in &replayable {
+                transaction.execute(
+                    "INSERT INTO webhook_jobs (app_id, url, app_key, signature, body, next_attempt_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![app_id, url, app_key, sign(body), body, now],
+                )?;
+                transaction.execute("DELETE FROM webhook_dead_letters WHERE id = ?1", [id])?;
+            }
+            transaction.commit()?;
+            Ok(replayable.len())
+        })
+        .await
+    }
+}
+
+fn job_from_row(row: &Row) -> Result<WebhookJob, rusqlite::Error> {
+    Ok(WebhookJob {
+        id: row.get(0)?,
+        app_id: row.get(1)?,
+        url: row.get(2)?,
+        app_key: row.get(3)?,
+        signature: row.get(4)?,
+        body: row.get(5)?,
+        attempts: row.get(6)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn store() -> WebhookStore {
+        WebhookStore::open(PathBuf::from(":memory:")).await.unwrap()
+    }
+
+    fn job(app_id: &str, url: &str, body: &str) -> NewWebhookJob {
+        NewWebhookJob {
+            app_id: app_id.to_string(),
+            url: url.to_string(),
+            app_key: "key".to_string(),
+            signature: format!("signed {}", body),
+            body: body.to_string(),
+        }
+    }
+
+    async fn due_bodies(store: &WebhookStore, now: i64) -> Vec<String> {
+        store
+            .due(now, 10, vec![], vec![])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|job| job.body)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn due_jobs_skip_claimed_apps_and_urls() {
+        let store = store().await;
+        store
+            .enqueue(
+                vec![
+                    job("1", "http://a", "first"),
+                    job("1", "http://b", "second"),
+                    job("2", "http://a", "third"),
+                ],
+                100,
+            )
+            .await
+            .unwrap();
+        assert!(due_bodies(&store, 99).await.is_empty());
+        assert_eq!(due_bodies(&store, 100).await, ["first", "second", "third"]);
+
+        let skipped = store
+            .due(100, 10, vec!["2".to_string()], vec!["http://b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].body, "first");
+
+        store.claim(skipped[0].id).await.unwrap();
+        assert_eq!(due_bodies(&store, 100).await, ["second", "third"]);
+        store.complete(skipped[0].id).await.unwrap();
+        assert_eq!(due_bodies(&store, 100).await, ["second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn failed_jobs_are_retried_then_dead_lettered() {
+        let store = store().await;
+        store
+            .enqueue(vec![job("1", "http://a", "event")], 100)
+            .await
+            .unwrap();
+        let id = store.due(100, 10, vec![], vec![]).await.unwrap()[0].id;
+
+        store.claim(id).await.unwrap();
+        store
+            .retry_later(id, "HTTP 500".to_string(), 200)
+            .await
+            .unwrap();
+        assert!(due_bodies(&store, 199).await.is_empty());
+        let retried = store.due(200, 10, vec![], vec![]).await.unwrap();
+        assert_eq!(retried[0].attempts, 1);
+
+        store.claim(id).await.unwrap();
+        store
+            .dead_letter(id, "HTTP 502".to_string(), 300)
+            .await
+            .unwrap();
+        assert!(due_bodies(&store, i64::MAX).await.is_empty());
+        let dead_letters = store.dead_letters("1", 10).await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].url, "http://a");
+        assert_eq!(dead_letters[0].body, "event");
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert_eq!(dead_letters[0].last_error, "HTTP 502");
+        assert_eq!(dead_letters[0].failed_at, 300);
+        assert!(store.dead_letters("2", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_queues_dead_letters_again_with_a_new_signature() {
+        let store = store().await;
+        store
+            .enqueue(
+                vec![
+                    job("1", "http://a", "first"),
+                    job("1", "http://a", "second"),
+                    job("2", "http://a", "other app"),
+                ],
+                100,
+            )
+            .await
+            .unwrap();
+        for job in store.due(100, 10, vec![], vec![]).await.unwrap() {
+            store
+                .dead_letter(job.id, "timeout".to_string(), 100)
+                .await
+                .unwrap();
+        }
+        let dead_letters = store.dead_letters("1", 10).await.unwrap();
+        let second = dead_letters[1].id;
+
+        let replayed = store
+            .replay(
+                "1",
+                Some(vec![second]),
+                "new-key".to_string(),
+                |body| format!("resigned {}", body),
+                500,
+            )
+            .await
+            .unwrap();
+        assert_eq!(replayed, 1);
+        let jobs = store.due(500, 10, vec![], vec![]).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].body, "second");
+        assert_eq!(jobs[0].app_key, "new-key");
+        assert_eq!(jobs[0].signature, "resigned second");
+        assert_eq!(jobs[0].attempts, 0);
+
+        let replayed = store
+            .replay(
+                "1",
+                None,
+                "new-key".to_string(),
+                |body| body.to_string(),
+                500,
+            )
+            .await
+            .unwrap();
+        assert_eq!(replayed, 1);
+        assert!(store.dead_letters("1", 10).await.unwrap().is_empty());
+        assert_eq!(store.dead_letters("2", 10).await.unwrap().len(), 1);
+        assert_eq!(due_bodies(&store, 500).await, ["second", "first"]);
+    }
+}
@@ -8,10 +8,22 @@ use axum::{
 use hyper::upgrade::Upgraded;
 use hyper_util::rt::TokioIo;
 use std::future::Future;
+use tokio::io::{ReadHalf, WriteHalf};
 
 pub type WebSocket = web_socket::WebSocket<TokioIo<Upgraded>>;
+pub type WebSocketReader = web_socket::WebSocket<ReadHalf<TokioIo<Upgraded>>>;
+pub type WebSocketWriter = web_socket::WebSocket<WriteHalf<TokioIo<Upgraded>>>;
 pub use web_socket;
 
+/// Splits an upgraded socket so frames can be read and written concurrently.
+pub fn split(socket: WebSocket) -> (WebSocketReader, WebSocketWriter) {
+    let (reader, writer) = tokio::io::split(socket.stream);
+    (
+        web_socket::WebSocket::server(reader),
+        web_socket::WebSocket::server(writer),
+    )
+}
+
 pub struct WebSocketUpgrade {
     sec_websocket_key: HeaderValue,
     on_upgrade: hyper::upgrade::OnUpgrade,
@@ -105,4 +117,4 @@ fn header_contains(headers: &HeaderMap, key: HeaderName, value: &'static str) ->
     } else {
         false
     }
-}
\ No newline at end of file
+}